@@ -0,0 +1,78 @@
+use super::prelude::*;
+use crate::serde_structs::structs::Op;
+use std::collections::HashSet;
+
+/// Pairs `(dest, src)` of copies `dest = id src` made on every path to some
+/// point, with neither variable assigned since. There is at most one pair for
+/// any `dest`.
+type Data = HashSet<(Var, Var)>;
+
+/// What `var` holds a copy of at some point, following a chain of copies
+/// back to the first variable in it
+pub fn source(data: &Data, var: Var) -> Var {
+    let mut var = var;
+    // bounded in case of a cycle
+    for _ in 0..data.len() {
+        match data.iter().find(|(dest, _)| *dest == var) {
+            Some((_, src)) => var = *src,
+            None => break,
+        }
+    }
+    var
+}
+
+/// Updates `data` for the effect of `instr`. A copy of a variable into
+/// itself changes nothing.
+pub fn step(data: &mut Data, instr: &Instr) {
+    let dest = match instr.dest() {
+        Some(dest) => dest,
+        None => return,
+    };
+
+    let copy = match instr {
+        Instr::Value { op: Op::Id, args, .. } => Some(args[0]),
+        _ => None,
+    };
+    if copy == Some(dest) {
+        return;
+    }
+
+    data.retain(|(x, y)| *x != dest && *y != dest);
+    if let Some(src) = copy {
+        data.insert((dest, src));
+    }
+}
+
+fn transfer(input: &Data, instrs: &Block, _id: BlockId) -> Data {
+    let mut data = input.clone();
+    for instr in instrs.iter() {
+        step(&mut data, instr);
+    }
+    data
+}
+
+fn merge(input: Vec<&Data>) -> Data {
+    let mut sets = input.into_iter();
+    let first = match sets.next() {
+        Some(first) => first.clone(),
+        None => return Data::new(),
+    };
+    sets.fold(first, |acc, x| &acc & x)
+}
+
+/// The copies available at the start and end of every block. This starts
+/// from every copy in the function being available everywhere but the entry,
+/// so that copies made before a loop are still known inside it.
+pub fn available_copies(cfg: &Cfg) -> Vec<AnalysisNode<Data>> {
+    let mut all = Data::new();
+    for id in cfg.ids() {
+        for instr in cfg[id].contents.iter() {
+            if let Instr::Value { op: Op::Id, dest, args, .. } = instr {
+                if args[0] != *dest {
+                    all.insert((*dest, args[0]));
+                }
+            }
+        }
+    }
+    worklist_solver(cfg, all, Data::new(), transfer, merge, Direction::Forward)
+}
@@ -1,28 +1,134 @@
 use super::prelude::*;
-use super::dehydrated::set_union;
-use std::collections::HashSet;
-use std::hash::Hash;
+use crate::arith;
+use crate::serde_structs::structs::{Literal, Op, Type};
+use std::collections::HashMap;
 
-type Data = HashSet<Value>;
+/// What is known about a variable at some point. Variables missing from the
+/// analysis data have no definition reaching that point yet.
+type Data = HashMap<Var, Value>;
 
-#[derive(Clone, Debug)]
-enum Value {
+#[derive(Clone, Copy, Debug)]
+pub enum Value {
     Unknown,
     Int(i64),
     Bool(bool),
     Float(f64)
 }
 
+// Floats are compared by their bits so that a NaN is equal to itself and the
+// solver can tell when nothing has changed
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Unknown, Value::Unknown) => true,
             (Value::Int(i1), Value::Int(i2)) => i1 == i2,
             (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
-            (Value::Float(f1), Value::Float(f2)) => f1 == f2,
+            (Value::Float(f1), Value::Float(f2)) => f1.to_bits() == f2.to_bits(),
             _ => false
         }
     }
 }
 
 impl Eq for Value {}
+
+impl From<Literal> for Value {
+    fn from(lit: Literal) -> Value {
+        match lit {
+            Literal::Int(i) => Value::Int(i),
+            Literal::Bool(b) => Value::Bool(b),
+            Literal::Float(x) => Value::Float(x),
+        }
+    }
+}
+
+impl Value {
+    pub fn from_literal(lit: &Literal, r_type: &Type) -> Value {
+        match (lit, r_type) {
+            (Literal::Int(i), Type::Float) => Value::Float(*i as f64),
+            (Literal::Int(i), _) => Value::Int(*i),
+            (Literal::Bool(b), _) => Value::Bool(*b),
+            (Literal::Float(x), _) => Value::Float(*x),
+        }
+    }
+
+    pub fn to_literal(self) -> Option<Literal> {
+        match self {
+            Value::Unknown => None,
+            Value::Int(i) => Some(Literal::Int(i)),
+            Value::Bool(b) => Some(Literal::Bool(b)),
+            Value::Float(x) => Some(Literal::Float(x)),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        self.to_literal().map_or_else(|| "?".to_string(), |x| x.to_string())
+    }
+
+    fn meet(self, other: Value) -> Value {
+        if self == other { self } else { Value::Unknown }
+    }
+}
+
+/// The result of `op` on constant arguments, or `None` if it can't be worked
+/// out ahead of time, as for a division by zero or arguments of the wrong
+/// type.
+pub fn evaluate(op: Op, args: &[Value]) -> Option<Value> {
+    let args: Option<Vec<Literal>> = args.iter().map(|x| x.to_literal()).collect();
+    arith::evaluate(op, &args?).map(Value::from)
+}
+
+/// Updates `data` for the effect of `instr`
+pub fn step(data: &mut Data, instr: &Instr) {
+    match instr {
+        Instr::Const { dest, r_type, value, .. } => {
+            data.insert(*dest, Value::from_literal(value, r_type));
+        }
+        Instr::Value { op: Op::Phi, dest, args, .. } => {
+            // Arguments come from different predecessors, so in SSA form each
+            // one is only defined along its own edge
+            match args.iter().filter_map(|x| data.get(x).copied()).reduce(Value::meet) {
+                Some(value) => data.insert(*dest, value),
+                None => data.remove(dest),
+            };
+        }
+        Instr::Value { op, dest, args, .. } if op.is_pure() => {
+            let values: Option<Vec<Value>> = args.iter().map(|x| data.get(x).copied()).collect();
+            match values {
+                Some(values) if values.contains(&Value::Unknown) => data.insert(*dest, Value::Unknown),
+                Some(values) => data.insert(*dest, evaluate(*op, &values).unwrap_or(Value::Unknown)),
+                // An argument with no definition yet says nothing about the result
+                None => data.remove(dest),
+            };
+        }
+        Instr::Value { dest, .. } => {
+            data.insert(*dest, Value::Unknown);
+        }
+        _ => {}
+    }
+}
+
+fn transfer(input: &Data, instrs: &Block, _id: BlockId) -> Data {
+    let mut data = input.clone();
+    for instr in instrs.iter() {
+        step(&mut data, instr);
+    }
+    data
+}
+
+fn merge(input: Vec<&Data>) -> Data {
+    let mut out = Data::new();
+    for data in input {
+        for (var, value) in data.iter() {
+            let merged = out.get(var).map_or(*value, |x| x.meet(*value));
+            out.insert(*var, merged);
+        }
+    }
+    out
+}
+
+/// The constant value of each variable at the start and end of every block.
+/// Function arguments are unknown.
+pub fn constant_propagation(cfg: &Cfg, args: &[FnHeaders]) -> Vec<AnalysisNode<Data>> {
+    let boundary: Data = args.iter().map(|x| (x.name, Value::Unknown)).collect();
+    worklist_solver(cfg, Data::new(), boundary, transfer, merge, Direction::Forward)
+}
@@ -0,0 +1,180 @@
+use super::prelude::*;
+use super::loops::Loop;
+use super::reaching_defns::{reaching_definitions, VarDef};
+use crate::serde_structs::structs::{Literal, Op};
+use std::collections::HashMap;
+
+/// An instruction, given by its block and position within that block
+pub type Site = (BlockId, usize);
+
+/// A variable changed by the same loop-invariant `step` on every iteration,
+/// through the only definition of it in the loop, `var = add var step` or
+/// `var = sub var step`.
+pub struct BasicInduction {
+    pub var: Var,
+    pub site: Site,
+    pub step: Var,
+    pub decreasing: bool,
+}
+
+/// How much a derived variable moves when its basic variable moves by one
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stride {
+    One,
+    Scaled(Var),
+}
+
+/// A variable that is a linear function of a basic induction variable, from
+/// a `mul`, `add` or `sub` with a loop-invariant value, or a `ptradd` onto a
+/// loop-invariant pointer.
+pub struct DerivedInduction {
+    pub var: Var,
+    pub site: Site,
+    pub basic: Var,
+    pub stride: Stride,
+    pub pointer: bool,
+    /// The derived variable this one is computed from, when it isn't
+    /// computed from `basic` directly
+    pub from: Option<Var>,
+}
+
+pub struct Inductions {
+    pub basic: Vec<BasicInduction>,
+    pub derived: Vec<DerivedInduction>,
+}
+
+impl Inductions {
+    pub fn basic_var(&self, var: Var) -> Option<&BasicInduction> {
+        self.basic.iter().find(|x| x.var == var)
+    }
+
+    pub fn derived_var(&self, var: Var) -> Option<&DerivedInduction> {
+        self.derived.iter().find(|x| x.var == var)
+    }
+}
+
+/// The value of `var` if the function only ever defines it with an int
+/// constant, and does so only once
+pub fn constant_int(cfg: &Cfg, var: Var) -> Option<i64> {
+    let mut defs = cfg.ids().flat_map(|id| cfg[id].contents.iter().filter(move |x| x.dest() == Some(var)));
+    match (defs.next(), defs.next()) {
+        (Some(Instr::Const { value: Literal::Int(val), .. }), None) => Some(*val),
+        _ => None,
+    }
+}
+
+/// The value `var` has coming into `natural_loop`, if it is always the same
+/// int constant
+pub fn initial_value(cfg: &Cfg, natural_loop: &Loop, args: &[FnHeaders], var: Var) -> Option<i64> {
+    let reaching = reaching_definitions(cfg, args);
+    let mut outside = reaching[natural_loop.header.index()].in_data.iter()
+        .filter(|VarDef(def, num)| *def == var && (*num == 0 || !natural_loop.contains(BlockId::from_index(num - 1))));
+    let block = match (outside.next(), outside.next()) {
+        (Some(VarDef(_, num)), None) if *num > 0 => BlockId::from_index(num - 1),
+        _ => return None,
+    };
+    match cfg[block].contents.iter().rev().find(|x| x.dest() == Some(var)) {
+        Some(Instr::Const { value: Literal::Int(val), .. }) => Some(*val),
+        _ => None,
+    }
+}
+
+/// Finds the induction variables of `natural_loop`. Values count as loop
+/// invariant only when nothing in the loop defines them, so constants are
+/// best hoisted out first.
+pub fn induction_variables(cfg: &Cfg, natural_loop: &Loop) -> Inductions {
+    let mut defs: HashMap<Var, Vec<Site>> = HashMap::new();
+    for id in natural_loop.body.iter() {
+        for (idx, instr) in cfg[*id].contents.iter().enumerate() {
+            if let Some(dest) = instr.dest() {
+                defs.entry(dest).or_default().push((*id, idx));
+            }
+        }
+    }
+    let invariant = |var: &Var| !defs.contains_key(var);
+
+    // Only variables with a single definition in the loop are considered, in
+    // order of that definition
+    let mut single: Vec<(Var, Site)> = defs.iter()
+        .filter(|(_, sites)| sites.len() == 1)
+        .map(|(var, sites)| (*var, sites[0]))
+        .collect();
+    single.sort_by_key(|(_, site)| *site);
+
+    let operation = |site: Site| -> Option<(Op, &[Var])> {
+        match &cfg[site.0].contents.0[site.1] {
+            Instr::Value { op, args, .. } if args.len() == 2 => Some((*op, &args[..])),
+            _ => None,
+        }
+    };
+
+    let mut basic = Vec::new();
+    for (var, site) in single.iter().copied() {
+        let step = match operation(site) {
+            Some((Op::Add, [a, b])) if *a == var && invariant(b) => Some((*b, false)),
+            Some((Op::Add, [a, b])) if *b == var && invariant(a) => Some((*a, false)),
+            Some((Op::Sub, [a, b])) if *a == var && invariant(b) => Some((*b, true)),
+            _ => None,
+        };
+        if let Some((step, decreasing)) = step {
+            basic.push(BasicInduction { var, site, step, decreasing });
+        }
+    }
+    let is_basic = |var: &Var| basic.iter().any(|x| x.var == *var);
+
+    let mut derived: Vec<DerivedInduction> = Vec::new();
+    for (var, site) in single.iter().copied() {
+        if is_basic(&var) {
+            continue;
+        }
+        let form = match operation(site) {
+            Some((Op::Mul, [a, b])) if is_basic(a) && invariant(b) => Some((*a, Stride::Scaled(*b), false)),
+            Some((Op::Mul, [a, b])) if is_basic(b) && invariant(a) => Some((*b, Stride::Scaled(*a), false)),
+            Some((Op::Add, [a, b])) if is_basic(a) && invariant(b) => Some((*a, Stride::One, false)),
+            Some((Op::Add, [a, b])) if is_basic(b) && invariant(a) => Some((*b, Stride::One, false)),
+            Some((Op::Sub, [a, b])) if is_basic(a) && invariant(b) => Some((*a, Stride::One, false)),
+            Some((Op::PtrAdd, [a, b])) if invariant(a) && is_basic(b) => Some((*b, Stride::One, true)),
+            _ => None,
+        };
+        if let Some((basic, stride, pointer)) = form {
+            derived.push(DerivedInduction { var, site, basic, stride, pointer, from: None });
+        }
+    }
+
+    // One more level, from the int variables derived directly. These have to
+    // follow the variable they come from in the same block with no update of
+    // the basic variable in between, so the two stay in step.
+    let mut second = Vec::new();
+    for (var, site) in single.iter().copied() {
+        if is_basic(&var) || derived.iter().any(|x| x.var == var) {
+            continue;
+        }
+        let first = |candidate: &Var| derived.iter().find(|x| {
+            x.var == *candidate && !x.pointer && x.site.0 == site.0 && x.site.1 < site.1
+                && basic.iter().all(|b| {
+                    b.var != x.basic || b.site.0 != site.0 || b.site.1 < x.site.1 || b.site.1 > site.1
+                })
+        });
+        let form = match operation(site) {
+            Some((Op::PtrAdd, [a, b])) if invariant(a) => first(b).map(|k| (k, k.stride, true)),
+            Some((Op::Add, [a, b])) if invariant(b) => first(a).map(|k| (k, k.stride, false)),
+            Some((Op::Add, [a, b])) if invariant(a) => first(b).map(|k| (k, k.stride, false)),
+            Some((Op::Sub, [a, b])) if invariant(b) => first(a).map(|k| (k, k.stride, false)),
+            Some((Op::Mul, [a, b])) if invariant(b) => first(a)
+                .filter(|k| k.stride == Stride::One)
+                .map(|k| (k, Stride::Scaled(*b), false)),
+            Some((Op::Mul, [a, b])) if invariant(a) => first(b)
+                .filter(|k| k.stride == Stride::One)
+                .map(|k| (k, Stride::Scaled(*a), false)),
+            _ => None,
+        };
+        if let Some((from, stride, pointer)) = form {
+            second.push(DerivedInduction {
+                var, site, basic: from.basic, stride, pointer, from: Some(from.var)
+            });
+        }
+    }
+    derived.extend(second);
+
+    Inductions { basic, derived }
+}
@@ -4,7 +4,7 @@ use std::collections::HashSet;
 
 type Data = HashSet<Var>;
 
-fn transfer(input: &Data, instrs: &Block, _idx: usize) -> Data {
+fn transfer(input: &Data, instrs: &Block, _id: BlockId) -> Data {
     let mut used_vars = Data::new();
     let mut killed = Data::new();
 
@@ -37,6 +37,6 @@ fn transfer(input: &Data, instrs: &Block, _idx: usize) -> Data {
     used_vars.union(&(input - &killed)).cloned().collect()
 }
 
-pub fn live_variables(nodes: &[Rc<Node>]) -> Vec<AnalysisNode<Data>> {
-    worklist_solver(nodes, Data::new(), transfer, set_union, Direction::Backward)
+pub fn live_variables(cfg: &Cfg) -> Vec<AnalysisNode<Data>> {
+    worklist_solver(cfg, Data::new(), Data::new(), transfer, set_union, Direction::Backward)
 }
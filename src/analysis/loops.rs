@@ -0,0 +1,132 @@
+use super::prelude::*;
+use crate::transformers::dominance::DominanceTree;
+
+/// A natural loop. The block lists are all sorted by block id.
+pub struct Loop {
+    pub header: BlockId,
+    /// Blocks inside the loop with a back edge to the header
+    pub latches: Vec<BlockId>,
+    /// Every block in the loop, the header included
+    pub body: Vec<BlockId>,
+    /// Blocks inside the loop with an edge leaving it
+    pub exiting: Vec<BlockId>,
+    /// Blocks outside the loop that are the targets of those edges
+    pub exits: Vec<BlockId>,
+    /// Index of the closest enclosing loop
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// One for outermost loops
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, id: BlockId) -> bool {
+        self.body.binary_search(&id).is_ok()
+    }
+}
+
+/// The natural loops of a function nested into a forest. Back edges sharing
+/// a header are merged into one loop, so every header has exactly one loop
+/// and loops are indexed in order of their header's block id.
+pub struct LoopForest {
+    loops: Vec<Loop>,
+}
+
+fn natural_loop(cfg: &Cfg, header: BlockId, latches: &[BlockId], dom_tree: &DominanceTree) -> Vec<BlockId> {
+    let mut body = vec![header];
+    let mut stack: Vec<BlockId> = Vec::new();
+    for latch in latches {
+        if !body.contains(latch) {
+            body.push(*latch);
+            stack.push(*latch);
+        }
+    }
+
+    // Walk backwards from the latches; the header stops the walk since it is
+    // already in the body
+    while let Some(id) = stack.pop() {
+        for pred in cfg.predecessors(id) {
+            if dom_tree.dominates(header, *pred) && !body.contains(pred) {
+                body.push(*pred);
+                stack.push(*pred);
+            }
+        }
+    }
+
+    body.sort();
+    body
+}
+
+impl LoopForest {
+    pub fn new(cfg: &Cfg, dom_tree: &DominanceTree) -> Self {
+        let mut headers: Vec<(BlockId, Vec<BlockId>)> = Vec::new();
+        for id in cfg.ids() {
+            for succ in cfg.successors(id) {
+                if dom_tree.dominates(succ, id) {
+                    match headers.iter_mut().find(|(header, _)| *header == succ) {
+                        Some((_, latches)) => latches.push(id),
+                        None => headers.push((succ, vec![id])),
+                    }
+                }
+            }
+        }
+        headers.sort_by_key(|(header, _)| *header);
+
+        let mut loops: Vec<Loop> = headers.into_iter().map(|(header, latches)| {
+            let body = natural_loop(cfg, header, &latches, dom_tree);
+
+            let mut exiting = Vec::new();
+            let mut exits = Vec::new();
+            for id in body.iter() {
+                for succ in cfg.successors(*id) {
+                    if body.binary_search(&succ).is_err() {
+                        if !exiting.contains(id) {
+                            exiting.push(*id);
+                        }
+                        if !exits.contains(&succ) {
+                            exits.push(succ);
+                        }
+                    }
+                }
+            }
+            exits.sort();
+
+            Loop { header, latches, body, exiting, exits, parent: None, children: Vec::new(), depth: 0 }
+        }).collect();
+
+        // Natural loops with different headers are either disjoint or nested,
+        // so the parent of a loop is the smallest other loop holding its header
+        for idx in 0..loops.len() {
+            let header = loops[idx].header;
+            loops[idx].parent = (0..loops.len())
+                .filter(|other| *other != idx && loops[*other].contains(header))
+                .min_by_key(|other| loops[*other].body.len());
+        }
+        for idx in 0..loops.len() {
+            if let Some(parent) = loops[idx].parent {
+                loops[parent].children.push(idx);
+            }
+            let mut depth = 1;
+            let mut current = loops[idx].parent;
+            while let Some(parent) = current {
+                depth += 1;
+                current = loops[parent].parent;
+            }
+            loops[idx].depth = depth;
+        }
+
+        LoopForest { loops }
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// Loop headers ordered so that nested loops come before the loops
+    /// around them
+    pub fn innermost_first(&self) -> Vec<BlockId> {
+        let mut headers: Vec<(usize, BlockId)> = self.loops.iter().map(|x| (x.depth, x.header)).collect();
+        headers.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        headers.into_iter().map(|(_, header)| header).collect()
+    }
+}
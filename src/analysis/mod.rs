@@ -1,20 +1,31 @@
 mod dataflow_core;
 pub mod reaching_defns;
 pub mod live_vars;
-mod cprop;
+pub mod loops;
+pub mod induction;
+pub mod cprop;
+pub mod copies;
 
 mod prelude {
     pub use super::dataflow_core::{worklist_solver, AnalysisNode, Direction};
-    pub use crate::transformers::cfg::{Node, Block};
-    pub use crate::serde_structs::structs::{Instr, Var, FnHeaders};
-    pub use std::rc::Rc;
-    pub use std::fmt::Display;
+    pub use crate::transformers::cfg::{Cfg, Block, BlockId};
+    pub use crate::serde_structs::structs::{Instr, Interner, Var, FnHeaders};
 }
 
-pub const ALLOWED_VALUES: &[&str] = &["reaching_defns", "live"];
+pub const ALLOWED_VALUES: &[&str] = &["reaching_defns", "live", "dom", "idom", "dom_tree", "frontier",
+                                     "pdom", "ipdom", "pdom_tree", "cdg", "loops",
+                                     "induction", "cprop"];
+
+/// Analysis results for one block, rendered as sorted strings for output
+pub struct BlockFacts {
+    pub input: Vec<String>,
+    pub output: Vec<String>,
+}
 
 pub use reaching_defns::reaching_definitions;
 pub use live_vars::live_variables;
+pub use cprop::constant_propagation;
+pub use loops::{Loop, LoopForest};
 
 // just add types!
 pub mod dehydrated {
@@ -28,17 +39,4 @@ pub mod dehydrated {
         }
         out
     }
-
-    pub fn set_intersection<T: Eq + Hash + Clone>(input: Vec<&HashSet<T>>) -> HashSet<T> {
-        let mut out = HashSet::<T>::new();
-
-        if !input.is_empty() {
-            for item in input[0].iter() {
-                if input.iter().all(|x| x.contains(item)) {
-                    out.insert(item.clone());
-                }
-            }
-        }
-        out
-    }
 }
@@ -0,0 +1,56 @@
+use crate::serde_structs::structs::{Literal, Op};
+
+// The meaning of Bril's arithmetic, comparison and logic operations, shared
+// by the interpreter and the passes that fold constants so that they always
+// agree. Integers are 64-bit and wrap around on overflow, and floats follow
+// IEEE 754, so any comparison involving a NaN is false.
+
+/// The result of an integer operation, or `None` if `op` isn't one or traps,
+/// as dividing by zero does.
+pub fn int_op(op: Op, lhs: i64, rhs: i64) -> Option<Literal> {
+    let result = match op {
+        Op::Add => Literal::Int(lhs.wrapping_add(rhs)),
+        Op::Sub => Literal::Int(lhs.wrapping_sub(rhs)),
+        Op::Mul => Literal::Int(lhs.wrapping_mul(rhs)),
+        Op::Div if rhs != 0 => Literal::Int(lhs.wrapping_div(rhs)),
+        Op::Eq => Literal::Bool(lhs == rhs),
+        Op::Lt => Literal::Bool(lhs < rhs),
+        Op::Gt => Literal::Bool(lhs > rhs),
+        Op::Le => Literal::Bool(lhs <= rhs),
+        Op::Ge => Literal::Bool(lhs >= rhs),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// The result of a float operation, or `None` if `op` isn't one
+pub fn float_op(op: Op, lhs: f64, rhs: f64) -> Option<Literal> {
+    let result = match op {
+        Op::FAdd => Literal::Float(lhs + rhs),
+        Op::FSub => Literal::Float(lhs - rhs),
+        Op::FMul => Literal::Float(lhs * rhs),
+        Op::FDiv => Literal::Float(lhs / rhs),
+        Op::FEq => Literal::Bool(lhs == rhs),
+        Op::FLt => Literal::Bool(lhs < rhs),
+        Op::FGt => Literal::Bool(lhs > rhs),
+        Op::FLe => Literal::Bool(lhs <= rhs),
+        Op::FGe => Literal::Bool(lhs >= rhs),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// The result of `op` on constant arguments, or `None` if the operation
+/// would trap, takes arguments of other types or isn't one of the pure
+/// operations on values.
+pub fn evaluate(op: Op, args: &[Literal]) -> Option<Literal> {
+    match (op, args) {
+        (Op::Id, [x]) => Some(x.clone()),
+        (Op::Not, [Literal::Bool(a)]) => Some(Literal::Bool(!a)),
+        (Op::And, [Literal::Bool(a), Literal::Bool(b)]) => Some(Literal::Bool(*a && *b)),
+        (Op::Or, [Literal::Bool(a), Literal::Bool(b)]) => Some(Literal::Bool(*a || *b)),
+        (_, [Literal::Int(a), Literal::Int(b)]) => int_op(op, *a, *b),
+        (_, [Literal::Float(a), Literal::Float(b)]) => float_op(op, *a, *b),
+        _ => None,
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::{self, Write};
+
+use crate::analysis::BlockFacts;
+use crate::serde_structs::structs::{CFGFunction, Instr, Interner};
+use crate::transformers::cfg::{BlockId, Link};
+use super::printer::write_instr;
+
+/// Analyses whose per-block results can be shown on the graph
+pub const ANNOTATIONS: &[&str] = &["reaching_defns", "live", "cprop"];
+
+// Escapes a line for use in a double quoted DOT label and left-justifies it
+fn push_line(label: &mut String, line: &str) {
+    for c in line.chars() {
+        match c {
+            '"' | '\\' => {
+                label.push('\\');
+                label.push(c);
+            }
+            _ => label.push(c),
+        }
+    }
+    label.push_str("\\l");
+}
+
+fn block_label(fun: &CFGFunction, names: &Interner, id: BlockId, facts: Option<&BlockFacts>) -> String {
+    let node = &fun.cfg()[id];
+    let mut label = String::new();
+
+    // Blocks without a label instruction still get a name to refer to
+    if !node.is_labeled() {
+        push_line(&mut label, &format!("[.{}]", names.resolve(node.label())));
+    }
+
+    let mut instrs = node.contents.iter().peekable();
+    if let Some(first) = instrs.next_if(|x| x.is_label()) {
+        push_line(&mut label, &InstrLine(names, first).to_string());
+    }
+    if let Some(facts) = facts {
+        push_line(&mut label, &format!("in: {}", facts.input.join(" ")));
+    }
+
+    for instr in instrs {
+        push_line(&mut label, &format!("  {}", InstrLine(names, instr)));
+    }
+
+    if let Some(facts) = facts {
+        push_line(&mut label, &format!("out: {}", facts.output.join(" ")));
+    }
+    label
+}
+
+struct InstrLine<'a>(&'a Interner, &'a Instr);
+
+impl fmt::Display for InstrLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instr(f, self.0, self.1)
+    }
+}
+
+/// Writes the CFG of `fun` as a DOT digraph with one node per block. Edges
+/// are styled by the kind of link: fallthroughs are dashed, jumps solid and
+/// branches colored green for true and red for false. Returns and falling off
+/// the end of the function go to a shared `exit` node. When `facts` is given,
+/// each block also shows its input and output sets.
+pub fn write_cfg_dot(out: &mut String, fun: &CFGFunction, names: &Interner,
+                     facts: Option<&[BlockFacts]>) -> fmt::Result {
+    let cfg = fun.cfg();
+    writeln!(out, "digraph \"{}\" {{", names.resolve(fun.name()))?;
+    writeln!(out, "  node [shape=box, fontname=\"monospace\"];")?;
+
+    for id in cfg.ids() {
+        let facts = facts.and_then(|x| x.get(id.index()));
+        writeln!(out, "  b{} [label=\"{}\"];", id.index(), block_label(fun, names, id, facts))?;
+    }
+
+    let mut exits = false;
+    for id in cfg.ids() {
+        let from = id.index();
+        match cfg[id].link() {
+            Link::Fallthrough(to) => {
+                writeln!(out, "  b{} -> b{} [style=dashed];", from, to.index())?;
+            }
+            Link::Jump(to) => {
+                writeln!(out, "  b{} -> b{};", from, to.index())?;
+            }
+            Link::Branch { true_branch, false_branch } => {
+                writeln!(out, "  b{} -> b{} [label=\"T\", color=green];", from, true_branch.index())?;
+                writeln!(out, "  b{} -> b{} [label=\"F\", color=red];", from, false_branch.index())?;
+            }
+            Link::Ret => {
+                exits = true;
+                writeln!(out, "  b{} -> exit [label=\"ret\"];", from)?;
+            }
+            Link::Exit => {
+                exits = true;
+                writeln!(out, "  b{} -> exit [style=dotted];", from)?;
+            }
+        }
+    }
+
+    if exits {
+        writeln!(out, "  exit [shape=point];")?;
+    }
+    writeln!(out, "}}")
+}
@@ -0,0 +1,8 @@
+pub mod dot;
+pub mod parser;
+pub mod printer;
+
+pub const INPUT_FORMATS: &[&str] = &["json", "text"];
+pub const OUTPUT_FORMATS: &[&str] = &["json", "text"];
+
+pub use parser::parse_program;
@@ -0,0 +1,446 @@
+use std::fmt::{self, Display};
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use crate::serde_structs::structs::{FnHeaders, FnName, Function, Instr, Interner, Label, Literal, Op, Program, Type, Var};
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        ParseError { line, column, message }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    FuncName(String),
+    LabelName(String),
+    Int(i64),
+    Float(f64),
+    Colon,
+    Semicolon,
+    Equals,
+    Comma,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LAngle,
+    RAngle,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "`{}`", s),
+            Token::FuncName(s) => write!(f, "`@{}`", s),
+            Token::LabelName(s) => write!(f, "`.{}`", s),
+            Token::Int(i) => write!(f, "`{}`", i),
+            Token::Float(x) => write!(f, "`{}`", x),
+            Token::Colon => write!(f, "`:`"),
+            Token::Semicolon => write!(f, "`;`"),
+            Token::Equals => write!(f, "`=`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::LBrace => write!(f, "`{{`"),
+            Token::RBrace => write!(f, "`}}`"),
+            Token::LAngle => write!(f, "`<`"),
+            Token::RAngle => write!(f, "`>`"),
+        }
+    }
+}
+
+type Operands = (Vec<Var>, Vec<FnName>, Vec<Label>);
+
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '%'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '%' || c == '.'
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Lexer {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while<P: Fn(char) -> bool>(&mut self, pred: P) -> String {
+        let mut out = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            out.push(c);
+            self.bump();
+        }
+        out
+    }
+
+    fn tokenize(mut self) -> Result<(Vec<Spanned>, (usize, usize)), ParseError> {
+        let mut tokens = Vec::new();
+
+        while let Some(&c) = self.chars.peek() {
+            let (line, column) = (self.line, self.column);
+
+            if c.is_whitespace() {
+                self.bump();
+                continue;
+            }
+
+            if c == '#' {
+                self.take_while(|c| c != '\n');
+                continue;
+            }
+
+            let token = match c {
+                ':' => { self.bump(); Token::Colon }
+                ';' => { self.bump(); Token::Semicolon }
+                '=' => { self.bump(); Token::Equals }
+                ',' => { self.bump(); Token::Comma }
+                '(' => { self.bump(); Token::LParen }
+                ')' => { self.bump(); Token::RParen }
+                '{' => { self.bump(); Token::LBrace }
+                '}' => { self.bump(); Token::RBrace }
+                '<' => { self.bump(); Token::LAngle }
+                '>' => { self.bump(); Token::RAngle }
+                '@' | '.' => {
+                    self.bump();
+                    let name = self.take_while(is_ident_char);
+                    if name.is_empty() {
+                        return Err(ParseError::new(line, column,
+                            format!("expected a name after `{}`", c)));
+                    }
+                    if c == '@' { Token::FuncName(name) } else { Token::LabelName(name) }
+                }
+                c if c == '-' || c.is_ascii_digit() => self.number(line, column)?,
+                c if is_ident_start(c) => Token::Ident(self.take_while(is_ident_char)),
+                c => {
+                    return Err(ParseError::new(line, column,
+                        format!("unexpected character `{}`", c)));
+                }
+            };
+
+            tokens.push(Spanned { token, line, column });
+        }
+
+        Ok((tokens, (self.line, self.column)))
+    }
+
+    fn number(&mut self, line: usize, column: usize) -> Result<Token, ParseError> {
+        let mut text = String::new();
+        if let Some('-') = self.chars.peek() {
+            text.push('-');
+            self.bump();
+        }
+        // `-inf` is the one negative literal that isn't written with digits
+        if self.chars.peek().copied().is_some_and(is_ident_start) {
+            text.push_str(&self.take_while(is_ident_char));
+            return match text.as_str() {
+                "-inf" => Ok(Token::Float(f64::NEG_INFINITY)),
+                _ => Err(ParseError::new(line, column, format!("malformed number `{}`", text))),
+            };
+        }
+        text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+
+        let mut is_float = false;
+        if let Some('.') = self.chars.peek() {
+            is_float = true;
+            text.push('.');
+            self.bump();
+            text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+        if let Some('e') | Some('E') = self.chars.peek() {
+            is_float = true;
+            text.push('e');
+            self.bump();
+            if let Some(&sign) = self.chars.peek() {
+                if sign == '-' || sign == '+' {
+                    text.push(sign);
+                    self.bump();
+                }
+            }
+            text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        }
+
+        let bad_number = || ParseError::new(line, column, format!("malformed number `{}`", text));
+        if is_float {
+            text.parse::<f64>().map(Token::Float).map_err(|_| bad_number())
+        } else {
+            text.parse::<i64>().map(Token::Int).map_err(|_| bad_number())
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    eof: (usize, usize),
+    names: Interner,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|x| &x.token)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|x| &x.token)
+    }
+
+    fn position(&self) -> (usize, usize) {
+        match self.tokens.get(self.pos) {
+            Some(spanned) => (spanned.line, spanned.column),
+            None => self.eof,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        let (line, column) = self.position();
+        Err(ParseError::new(line, column, message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        match self.peek() {
+            Some(tok) => self.error(format!("expected {}, found {}", expected, tok)),
+            None => self.error(format!("expected {}, found end of input", expected)),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).map(|x| x.token.clone());
+        if tok.is_some() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        if self.eat(&expected) {
+            Ok(())
+        } else {
+            self.unexpected(&expected.to_string())
+        }
+    }
+
+    fn ident(&mut self, what: &str) -> Result<String, ParseError> {
+        if let Some(Token::Ident(name)) = self.peek() {
+            let name = name.clone();
+            self.pos += 1;
+            Ok(name)
+        } else {
+            self.unexpected(what)
+        }
+    }
+
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let mut functions = Vec::new();
+        while self.peek().is_some() {
+            functions.push(self.function()?);
+        }
+        Ok(Program::new(functions, std::mem::take(&mut self.names)))
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let name = match self.peek().cloned() {
+            Some(Token::FuncName(name)) => FnName(self.names.intern(&name)),
+            _ => return self.unexpected("a function name"),
+        };
+        self.pos += 1;
+
+        let mut args = Vec::new();
+        if self.eat(&Token::LParen) && !self.eat(&Token::RParen) {
+            loop {
+                let arg = self.ident("an argument name")?;
+                self.expect(Token::Colon)?;
+                let r_type = self.r_type()?;
+                args.push(FnHeaders { name: Var(self.names.intern(&arg)), r_type });
+
+                if self.eat(&Token::RParen) {
+                    break;
+                }
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        let r_type = if self.eat(&Token::Colon) {
+            Some(self.r_type()?)
+        } else {
+            None
+        };
+
+        self.expect(Token::LBrace)?;
+        let mut instrs = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            instrs.push(self.instr()?);
+        }
+
+        Ok(Function::new(name, args, r_type, instrs))
+    }
+
+    fn r_type(&mut self) -> Result<Type, ParseError> {
+        let (line, column) = self.position();
+        let name = self.ident("a type")?;
+        match name.as_str() {
+            "int" => Ok(Type::Int),
+            "bool" => Ok(Type::Bool),
+            "float" => Ok(Type::Float),
+            "ptr" => {
+                self.expect(Token::LAngle)?;
+                let inner = self.r_type()?;
+                self.expect(Token::RAngle)?;
+                Ok(Type::Ptr(Box::new(inner)))
+            }
+            _ => Err(ParseError::new(line, column, format!("unknown type `{}`", name))),
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, ParseError> {
+        let (line, column) = self.position();
+        let name = self.ident("an operation")?;
+        Op::from_str(&name).map_err(|msg| ParseError::new(line, column, msg))
+    }
+
+    fn instr(&mut self) -> Result<Instr, ParseError> {
+        if let Some(Token::LabelName(label)) = self.peek().cloned() {
+            let label = Label(self.names.intern(&label));
+            self.pos += 1;
+            self.expect(Token::Colon)?;
+            return Ok(Instr::Label { label });
+        }
+
+        match (self.peek(), self.peek_nth(1)) {
+            (Some(Token::Ident(_)), Some(Token::Colon)) => {
+                let dest = self.ident("a destination")?;
+                let dest = Var(self.names.intern(&dest));
+                self.expect(Token::Colon)?;
+                let r_type = self.r_type()?;
+                self.expect(Token::Equals)?;
+
+                let op = self.op()?;
+                if op == Op::Const {
+                    let value = self.literal(&r_type)?;
+                    self.expect(Token::Semicolon)?;
+                    Ok(Instr::Const { op, dest, r_type, value })
+                } else {
+                    let (args, funcs, labels) = self.operands()?;
+                    Ok(Instr::Value { op, dest, r_type, args, funcs, labels })
+                }
+            }
+            (Some(Token::Ident(_)), Some(Token::Equals)) => {
+                self.error("missing type annotation on destination".to_string())
+            }
+            (Some(Token::Ident(_)), _) => {
+                let (line, column) = self.position();
+                let op = self.op()?;
+                if op == Op::Const {
+                    return Err(ParseError::new(line, column,
+                        "`const` requires a destination".to_string()));
+                }
+                let (args, funcs, labels) = self.operands()?;
+                Ok(Instr::Effect { op, args, funcs, labels })
+            }
+            _ => self.unexpected("an instruction or label"),
+        }
+    }
+
+    fn operands(&mut self) -> Result<Operands, ParseError> {
+        let mut args = Vec::new();
+        let mut funcs = Vec::new();
+        let mut labels = Vec::new();
+
+        loop {
+            match self.peek().cloned() {
+                Some(Token::Semicolon) => {
+                    self.pos += 1;
+                    return Ok((args, funcs, labels));
+                }
+                Some(Token::Ident(name)) => args.push(Var(self.names.intern(&name))),
+                Some(Token::FuncName(name)) => funcs.push(FnName(self.names.intern(&name))),
+                Some(Token::LabelName(name)) => labels.push(Label(self.names.intern(&name))),
+                _ => return self.unexpected("an argument or `;`"),
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, r_type: &Type) -> Result<Literal, ParseError> {
+        let (line, column) = self.position();
+        let mismatch = |tok: &Token| ParseError::new(line, column,
+            format!("literal {} does not match type {}", tok, r_type));
+
+        let tok = match self.next() {
+            Some(tok) => tok,
+            None => return self.unexpected("a literal"),
+        };
+
+        match (r_type, &tok) {
+            (Type::Int, Token::Int(i)) => Ok(Literal::Int(*i)),
+            (Type::Float, Token::Int(i)) => Ok(Literal::Float(*i as f64)),
+            (Type::Float, Token::Float(x)) => Ok(Literal::Float(*x)),
+            (Type::Float, Token::Ident(x)) if x == "nan" => Ok(Literal::Float(f64::NAN)),
+            (Type::Float, Token::Ident(x)) if x == "inf" => Ok(Literal::Float(f64::INFINITY)),
+            (Type::Bool, Token::Ident(b)) if b == "true" => Ok(Literal::Bool(true)),
+            (Type::Bool, Token::Ident(b)) if b == "false" => Ok(Literal::Bool(false)),
+            (_, Token::Int(_)) | (_, Token::Float(_)) | (_, Token::Ident(_)) => Err(mismatch(&tok)),
+            _ => Err(ParseError::new(line, column, format!("expected a literal, found {}", tok))),
+        }
+    }
+}
+
+/// Parses the textual form of a bril program, as accepted by `bril2json`.
+pub fn parse_program(input: &str) -> Result<Program, ParseError> {
+    let (tokens, eof) = Lexer::new(input).tokenize()?;
+    let mut parser = Parser { tokens, pos: 0, eof, names: Interner::new() };
+    parser.program()
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+
+use crate::serde_structs::structs::{FnHeaders, FnName, Instr, Interner, Label, Program, Type, Var};
+
+/// Writes a single instruction in the form `bril2txt` produces, minus the
+/// leading indentation. Labels are written as `.name:` and everything else is
+/// terminated with a `;`.
+pub fn write_instr(f: &mut fmt::Formatter<'_>, names: &Interner, instr: &Instr) -> fmt::Result {
+    match instr {
+        Instr::Label { label } => write!(f, ".{}:", names.resolve(*label)),
+        Instr::Const { op, dest, r_type, value } => {
+            write!(f, "{}: {} = {} {};", names.resolve(*dest), r_type, op, value)
+        }
+        Instr::Value { op, dest, r_type, args, funcs, labels } => {
+            write!(f, "{}: {} = {}", names.resolve(*dest), r_type, op)?;
+            write_operands(f, names, args, funcs, labels)?;
+            write!(f, ";")
+        }
+        Instr::Effect { op, args, funcs, labels } => {
+            write!(f, "{}", op)?;
+            write_operands(f, names, args, funcs, labels)?;
+            write!(f, ";")
+        }
+    }
+}
+
+fn write_operands(f: &mut fmt::Formatter<'_>, names: &Interner, args: &[Var], funcs: &[FnName],
+                  labels: &[Label]) -> fmt::Result {
+    for func in funcs.iter() {
+        write!(f, " @{}", names.resolve(*func))?;
+    }
+    for arg in args.iter() {
+        write!(f, " {}", names.resolve(*arg))?;
+    }
+    for label in labels.iter() {
+        write!(f, " .{}", names.resolve(*label))?;
+    }
+    Ok(())
+}
+
+pub fn write_function<'a, I>(f: &mut fmt::Formatter<'_>, names: &Interner, name: &FnName,
+                             args: &[FnHeaders], r_type: Option<&Type>, instrs: I) -> fmt::Result
+    where I: Iterator<Item = &'a Instr> {
+    write!(f, "@{}", names.resolve(*name))?;
+    if !args.is_empty() {
+        write!(f, "(")?;
+        for (idx, arg) in args.iter().enumerate() {
+            if idx != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", names.resolve(arg.name), arg.r_type)?;
+        }
+        write!(f, ")")?;
+    }
+    if let Some(r_type) = r_type {
+        write!(f, ": {}", r_type)?;
+    }
+    writeln!(f, " {{")?;
+
+    for instr in instrs {
+        if !instr.is_label() {
+            write!(f, "  ")?;
+        }
+        write_instr(f, names, instr)?;
+        writeln!(f)?;
+    }
+    write!(f, "}}")
+}
+
+pub fn write_program(f: &mut fmt::Formatter<'_>, prog: &Program) -> fmt::Result {
+    for (idx, fun) in prog.functions.iter().enumerate() {
+        if idx != 0 {
+            writeln!(f)?;
+        }
+        write_function(f, &prog.names, &fun.name(), fun.args(), fun.return_type(), fun.instrs().iter())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use super::value::Value;
+use super::InterpError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pointer {
+    base: usize,
+    offset: i64,
+}
+
+impl Pointer {
+    pub fn add(&self, offset: i64) -> Pointer {
+        Pointer { base: self.base, offset: self.offset.wrapping_add(offset) }
+    }
+}
+
+impl Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<ptr {}+{}>", self.base, self.offset)
+    }
+}
+
+/// Allocations made by `alloc`, keyed by base address. Each slot starts out
+/// uninitialized and reading it before a `store` is an error, as in brili.
+#[derive(Default)]
+pub struct Heap {
+    allocations: HashMap<usize, Vec<Option<Value>>>,
+    next_base: usize,
+}
+
+impl Heap {
+    pub fn alloc(&mut self, amount: i64) -> Result<Pointer, InterpError> {
+        if amount <= 0 {
+            return Err(InterpError::new(format!("cannot allocate {} entries", amount)));
+        }
+        let base = self.next_base;
+        self.next_base += 1;
+        self.allocations.insert(base, vec![None; amount as usize]);
+        Ok(Pointer { base, offset: 0 })
+    }
+
+    pub fn free(&mut self, ptr: Pointer) -> Result<(), InterpError> {
+        if ptr.offset != 0 {
+            return Err(InterpError::new("tried to free an offset pointer".to_string()));
+        }
+        match self.allocations.remove(&ptr.base) {
+            Some(_) => Ok(()),
+            None => Err(InterpError::new("tried to free memory that is not allocated".to_string())),
+        }
+    }
+
+    fn slot(&mut self, ptr: Pointer) -> Result<&mut Option<Value>, InterpError> {
+        let allocation = self.allocations.get_mut(&ptr.base)
+            .ok_or_else(|| InterpError::new("access to memory that is not allocated".to_string()))?;
+        if ptr.offset < 0 || ptr.offset as usize >= allocation.len() {
+            return Err(InterpError::new(format!("out of bounds access at offset {}", ptr.offset)));
+        }
+        Ok(&mut allocation[ptr.offset as usize])
+    }
+
+    pub fn load(&mut self, ptr: Pointer) -> Result<Value, InterpError> {
+        self.slot(ptr)?.ok_or_else(|| InterpError::new("load from uninitialized memory".to_string()))
+    }
+
+    pub fn store(&mut self, ptr: Pointer, value: Value) -> Result<(), InterpError> {
+        *self.slot(ptr)? = Some(value);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+}
@@ -0,0 +1,233 @@
+mod memory;
+mod value;
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::Write;
+
+use crate::arith;
+use crate::serde_structs::structs::{FnName, Function, Instr, Interner, Label, Op, Program, Var};
+use memory::Heap;
+use value::Value;
+
+#[derive(Debug)]
+pub struct InterpError(String);
+
+impl InterpError {
+    fn new(message: String) -> Self {
+        InterpError(message)
+    }
+}
+
+impl Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.0)
+    }
+}
+
+type Env = HashMap<Var, Value>;
+
+struct FunctionInfo<'a> {
+    fun: &'a Function,
+    labels: HashMap<Label, usize>,
+}
+
+impl<'a> FunctionInfo<'a> {
+    fn new(fun: &'a Function) -> Self {
+        let labels = fun.instrs().iter().enumerate()
+            .filter_map(|(idx, instr)| instr.extract_label().map(|label| (label, idx)))
+            .collect();
+        FunctionInfo { fun, labels }
+    }
+
+    fn target(&self, label: &Label, names: &Interner) -> Result<usize, InterpError> {
+        self.labels.get(label).copied()
+            .ok_or_else(|| InterpError::new(format!("unknown label .{}", names.resolve(*label))))
+    }
+}
+
+struct State<W: Write> {
+    heap: Heap,
+    out: W,
+    instr_count: u64,
+}
+
+struct Interpreter<'a> {
+    functions: HashMap<FnName, FunctionInfo<'a>>,
+    names: &'a Interner,
+}
+
+impl<'a> Interpreter<'a> {
+    fn lookup(&self, env: &Env, var: &Var) -> Result<Value, InterpError> {
+        env.get(var).copied()
+            .ok_or_else(|| InterpError::new(format!("undefined variable {}", self.names.resolve(*var))))
+    }
+
+    fn call<W: Write>(&self, state: &mut State<W>, name: &FnName, args: Vec<Value>)
+        -> Result<Option<Value>, InterpError> {
+        let info = self.functions.get(name)
+            .ok_or_else(|| InterpError::new(format!("undefined function @{}", self.names.resolve(*name))))?;
+        let fun = info.fun;
+
+        if fun.args().len() != args.len() {
+            return Err(InterpError::new(format!("@{} expects {} arguments, got {}",
+                self.names.resolve(*name), fun.args().len(), args.len())));
+        }
+
+        let mut env = Env::new();
+        for (header, value) in fun.args().iter().zip(args) {
+            if !value.matches(&header.r_type) {
+                return Err(InterpError::new(format!("argument {} of @{} is not of type {}",
+                    self.names.resolve(header.name), self.names.resolve(*name), header.r_type)));
+            }
+            env.insert(header.name, value);
+        }
+
+        let instrs = fun.instrs();
+        let mut pc = 0;
+        let mut current_label: Option<Label> = None;
+        let mut last_label: Option<Label> = None;
+
+        while let Some(instr) = instrs.get(pc) {
+            pc += 1;
+            match instr {
+                Instr::Label { label } => {
+                    last_label = current_label;
+                    current_label = Some(*label);
+                    continue;
+                }
+                Instr::Const { dest, r_type, value, .. } => {
+                    env.insert(*dest, Value::from_literal(value, r_type));
+                }
+                Instr::Value { op: Op::Phi, dest, args, labels, .. } => {
+                    let last = last_label
+                        .ok_or_else(|| InterpError::new("phi executed with no last label".to_string()))?;
+                    // Phis with no matching or undefined incoming value leave the
+                    // destination undefined
+                    let incoming = labels.iter().position(|x| *x == last)
+                        .and_then(|idx| args.get(idx))
+                        .and_then(|arg| env.get(arg).copied());
+                    match incoming {
+                        Some(value) => { env.insert(*dest, value); }
+                        None => { env.remove(dest); }
+                    }
+                }
+                Instr::Value { op, dest, args, funcs, .. } => {
+                    let value = self.eval_value(state, &env, *op, args, funcs)?;
+                    env.insert(*dest, value);
+                }
+                Instr::Effect { op, args, funcs, labels } => {
+                    state.instr_count += 1;
+                    match op {
+                        Op::Jmp => {
+                            pc = info.target(&labels[0], self.names)?;
+                        }
+                        Op::Br => {
+                            let cond = self.lookup(&env, &args[0])?.as_bool()?;
+                            pc = info.target(&labels[if cond { 0 } else { 1 }], self.names)?;
+                        }
+                        Op::Ret => {
+                            return match args.first() {
+                                Some(arg) => Ok(Some(self.lookup(&env, arg)?)),
+                                None => Ok(None),
+                            };
+                        }
+                        Op::Print => {
+                            let values = args.iter().map(|x| self.lookup(&env, x).map(|v| v.to_string()))
+                                .collect::<Result<Vec<String>, InterpError>>()?;
+                            writeln!(state.out, "{}", values.join(" "))
+                                .map_err(|err| InterpError::new(err.to_string()))?;
+                        }
+                        Op::Call => {
+                            let args = args.iter().map(|x| self.lookup(&env, x))
+                                .collect::<Result<Vec<Value>, InterpError>>()?;
+                            self.call(state, &funcs[0], args)?;
+                        }
+                        Op::Store => {
+                            let ptr = self.lookup(&env, &args[0])?.as_pointer()?;
+                            state.heap.store(ptr, self.lookup(&env, &args[1])?)?;
+                        }
+                        Op::Free => {
+                            let ptr = self.lookup(&env, &args[0])?.as_pointer()?;
+                            state.heap.free(ptr)?;
+                        }
+                        Op::Nop => {}
+                        _ => return Err(InterpError::new(format!("{} cannot be used as an effect", op))),
+                    }
+                    continue;
+                }
+            }
+            state.instr_count += 1;
+        }
+
+        Ok(None)
+    }
+
+    fn eval_value<W: Write>(&self, state: &mut State<W>, env: &Env, op: Op, args: &[Var], funcs: &[FnName])
+        -> Result<Value, InterpError> {
+        let arg = |idx: usize| -> Result<Value, InterpError> {
+            let var = args.get(idx)
+                .ok_or_else(|| InterpError::new(format!("{} is missing argument {}", op, idx)))?;
+            self.lookup(env, var)
+        };
+
+        let value = match op {
+            Op::Id => arg(0)?,
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Eq | Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+                arith::int_op(op, arg(0)?.as_int()?, arg(1)?.as_int()?)
+                    .map(Value::from)
+                    .ok_or_else(|| InterpError::new("division by zero".to_string()))?
+            }
+            Op::FAdd | Op::FSub | Op::FMul | Op::FDiv | Op::FEq | Op::FLt | Op::FGt | Op::FLe | Op::FGe => {
+                arith::float_op(op, arg(0)?.as_float()?, arg(1)?.as_float()?).map(Value::from).unwrap()
+            }
+            Op::Not => Value::Bool(!arg(0)?.as_bool()?),
+            Op::And => Value::Bool(arg(0)?.as_bool()? && arg(1)?.as_bool()?),
+            Op::Or => Value::Bool(arg(0)?.as_bool()? || arg(1)?.as_bool()?),
+            Op::Call => {
+                let values = args.iter().map(|x| self.lookup(env, x))
+                    .collect::<Result<Vec<Value>, InterpError>>()?;
+                self.call(state, &funcs[0], values)?
+                    .ok_or_else(|| InterpError::new(format!("@{} did not return a value",
+                        self.names.resolve(funcs[0]))))?
+            }
+            Op::Alloc => Value::Pointer(state.heap.alloc(arg(0)?.as_int()?)?),
+            Op::Load => state.heap.load(arg(0)?.as_pointer()?)?,
+            Op::PtrAdd => Value::Pointer(arg(0)?.as_pointer()?.add(arg(1)?.as_int()?)),
+            _ => return Err(InterpError::new(format!("{} does not produce a value", op))),
+        };
+        Ok(value)
+    }
+}
+
+/// Runs `main` with the given command line arguments, writing anything it
+/// prints to `out`. Returns the number of instructions executed, which is the
+/// same count `brili -p` reports.
+pub fn run_program<W: Write>(prog: &Program, args: &[String], out: W) -> Result<u64, InterpError> {
+    let interp = Interpreter {
+        functions: prog.functions.iter().map(|x| (x.name(), FunctionInfo::new(x))).collect(),
+        names: &prog.names,
+    };
+
+    let main_name = prog.names.lookup("main");
+    let main = prog.functions.iter().find(|x| Some(x.name().0) == main_name)
+        .ok_or_else(|| InterpError::new("no main function".to_string()))?;
+
+    if main.args().len() != args.len() {
+        return Err(InterpError::new(format!("main expects {} arguments, got {}",
+            main.args().len(), args.len())));
+    }
+    let args = main.args().iter().zip(args.iter())
+        .map(|(header, arg)| Value::parse_arg(arg, &header.r_type))
+        .collect::<Result<Vec<Value>, InterpError>>()?;
+
+    let mut state = State { heap: Heap::default(), out, instr_count: 0 };
+    interp.call(&mut state, &main.name(), args)?;
+
+    if !state.heap.is_empty() {
+        return Err(InterpError::new("some memory locations have not been freed by the end of execution".to_string()));
+    }
+
+    state.out.flush().map_err(|err| InterpError::new(err.to_string()))?;
+    Ok(state.instr_count)
+}
@@ -0,0 +1,107 @@
+use std::fmt::{self, Display};
+
+use super::memory::Pointer;
+use super::InterpError;
+use crate::serde_structs::structs::{Literal, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Float(f64),
+    Pointer(Pointer),
+}
+
+impl From<Literal> for Value {
+    fn from(lit: Literal) -> Value {
+        match lit {
+            Literal::Int(i) => Value::Int(i),
+            Literal::Bool(b) => Value::Bool(b),
+            Literal::Float(x) => Value::Float(x),
+        }
+    }
+}
+
+impl Value {
+    pub fn from_literal(lit: &Literal, r_type: &Type) -> Value {
+        match (lit, r_type) {
+            (Literal::Int(i), Type::Float) => Value::Float(*i as f64),
+            (Literal::Int(i), _) => Value::Int(*i),
+            (Literal::Bool(b), _) => Value::Bool(*b),
+            (Literal::Float(x), _) => Value::Float(*x),
+        }
+    }
+
+    /// Parses a command line argument for `main` according to its declared type.
+    pub fn parse_arg(arg: &str, r_type: &Type) -> Result<Value, InterpError> {
+        let parsed = match r_type {
+            Type::Int => arg.parse::<i64>().ok().map(Value::Int),
+            Type::Bool => arg.parse::<bool>().ok().map(Value::Bool),
+            Type::Float => arg.parse::<f64>().ok().map(Value::Float),
+            Type::Ptr(_) => None,
+        };
+        parsed.ok_or_else(|| InterpError::new(format!("cannot interpret `{}` as {}", arg, r_type)))
+    }
+
+    pub fn matches(&self, r_type: &Type) -> bool {
+        matches!((self, r_type),
+            (Value::Int(_), Type::Int)
+            | (Value::Bool(_), Type::Bool)
+            | (Value::Float(_), Type::Float)
+            | (Value::Pointer(_), Type::Ptr(_)))
+    }
+
+    pub fn as_int(&self) -> Result<i64, InterpError> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            _ => Err(InterpError::new(format!("expected int, found {}", self.type_name()))),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, InterpError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(InterpError::new(format!("expected bool, found {}", self.type_name()))),
+        }
+    }
+
+    pub fn as_float(&self) -> Result<f64, InterpError> {
+        match self {
+            Value::Float(x) => Ok(*x),
+            _ => Err(InterpError::new(format!("expected float, found {}", self.type_name()))),
+        }
+    }
+
+    pub fn as_pointer(&self) -> Result<Pointer, InterpError> {
+        match self {
+            Value::Pointer(p) => Ok(*p),
+            _ => Err(InterpError::new(format!("expected ptr, found {}", self.type_name()))),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::Float(_) => "float",
+            Value::Pointer(_) => "ptr",
+        }
+    }
+}
+
+// Output formatting follows brili so that program output can be compared
+// directly against the reference interpreter
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Float(x) if x.is_nan() => write!(f, "NaN"),
+            Value::Float(x) if x.is_infinite() => {
+                write!(f, "{}Infinity", if *x < 0.0 { "-" } else { "" })
+            }
+            Value::Float(x) => write!(f, "{:.17}", x),
+            Value::Pointer(p) => write!(f, "{}", p),
+        }
+    }
+}
@@ -1,12 +1,15 @@
 mod serde_structs;
 mod transformers;
 mod analysis;
+mod formats;
+mod interpreter;
+mod arith;
 
 use std::io::{self, Read};
 use std::process::exit;
 use serde_structs::structs::{Program, CFGFunction};
-use clap::{Arg, App, SubCommand};
-use transformers::config::ConfigOptions;
+use clap::{Arg, App, AppSettings, SubCommand};
+use transformers::config;
 
 
 
@@ -26,83 +29,50 @@ fn get_stdin() -> String {
     buffer
 }
 
-fn apply_transformations(mut prog: Program, conf: ConfigOptions) -> Program {
-
-    if conf.g_tdce {
-        // eprintln!("Applying trivial global dce");
-        for fun in prog.functions.iter_mut() {
-            fun.g_tcde()
+fn read_program(buffer: &str, input_format: Option<&str>) -> Program {
+    match input_format {
+        Some("text") => {
+            formats::parse_program(buffer).unwrap_or_else(|err| {
+                eprintln!("Unable to parse program: {}", err);
+                exit(1)
+            })
         }
+        _ => Program::from_json(buffer).unwrap()
     }
+}
 
-    if conf.lvn.run_lvn() || conf.l_tdce || conf.orphan_block || conf.to_ssa
-        || conf.from_ssa {
-        let mut cfg = prog.determine_cfg();
-        // for fun in cfg.functions.iter() {
-        //     eprintln!("{:?}", fun)
-        // }
-        // eprintln!("\n\n\n\n\n");
-        if conf.orphan_block {
-        // eprintln!("Applying orphan block removal");
-            for fun in cfg.functions.iter_mut() {
-                fun.drop_orphan_blocks()
-            }
-        }
-
-        if conf.l_tdce {
-        // eprintln!("Applying trivial local dce");
-            for fun in cfg.functions.iter_mut() {
-                fun.apply_basic_dce()
-            }
-        }
-
-        if conf.lvn.run_solo() {
-        // eprintln!("Applying lvn solo");
-            for fun in cfg.functions.iter_mut() {
-                fun.apply_lvn()
-            }
-        }
-
-        if conf.lvn.run_normal() {
-        // eprintln!("Applying lvn normal");
-            for fun in cfg.functions.iter_mut() {
-                fun.apply_lvn();
-                fun.apply_basic_dce()
-            }
-        }
-
-        if conf.to_ssa {
-            for fun in cfg.functions.iter_mut() {
-                if !conf.orphan_block{
-                    fun.drop_orphan_blocks()
-                }
-                fun.to_ssa()
-            }
-        }
-
-        if conf.from_ssa {
-            for fun in cfg.functions.iter_mut() {
-                fun.from_ssa()
-            }
-        }
-
-
-        prog = cfg.make_serializeable()
+fn emit_program(prog: Program, output_format: &str) {
+    match output_format {
+        "text" => println!("{}", prog),
+        _ => println!("{}", prog.into_json().ok().unwrap_or_default())
     }
+}
 
-    if conf.lvn.run_normal() {
-        for fun in prog.functions.iter_mut() {
-            fun.g_tcde()
-        }
+// Validates the numeric pass parameters
+fn is_count(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("{} is not a non-negative integer", value)),
     }
-
-    prog
 }
 
 fn main() {
     let matches = App::new("Bril Toolkit").version("0.1")
                     .author("Griffin Berlstein <griffin@berlste.in>")
                     .about("A toolkit for bril transformations")
+                    .arg(Arg::with_name("input-format")
+                         .long("input-format")
+                         .takes_value(true)
+                         .global(true)
+                         .possible_values(formats::INPUT_FORMATS)
+                         .default_value("json")
+                    )
+                    .arg(Arg::with_name("output-format")
+                         .long("output-format")
+                         .takes_value(true)
+                         .global(true)
+                         .possible_values(formats::OUTPUT_FORMATS)
+                    )
                     .subcommand(
                     SubCommand::with_name("transform")
                                 .version("0.1")
@@ -113,7 +83,31 @@ fn main() {
                                 .long("optimizations")
                                 .multiple(true)
                                 .takes_value(true)
-                                .possible_values(&transformers::config::ALLOWED_VALUES)
+                                .possible_values(config::ALLOWED_VALUES)
+                                )
+                                .arg(Arg::with_name("opt-level")
+                                .short("O")
+                                .takes_value(true)
+                                .possible_values(config::OPT_LEVELS)
+                                .help("Run a preset pipeline before any -o passes")
+                                )
+                                .arg(Arg::with_name("unroll-factor")
+                                .long("unroll-factor")
+                                .takes_value(true)
+                                .validator(is_count)
+                                .help("How many copies of a loop body unroll makes [default: 4]")
+                                )
+                                .arg(Arg::with_name("peel-count")
+                                .long("peel-count")
+                                .takes_value(true)
+                                .validator(is_count)
+                                .help("How many iterations peel takes off the front of a loop [default: 1]")
+                                )
+                                .arg(Arg::with_name("unswitch-budget")
+                                .long("unswitch-budget")
+                                .takes_value(true)
+                                .validator(is_count)
+                                .help("How many instructions unswitch may add to a function [default: 100]")
                     ))
                     .subcommand(
                         SubCommand::with_name("analyze")
@@ -125,9 +119,44 @@ fn main() {
                                      .long("analysis")
                                      .index(1)
                                      .takes_value(true)
-                                     .possible_values(&analysis::ALLOWED_VALUES)
+                                     .possible_values(analysis::ALLOWED_VALUES)
                                      .required(true)
                     ))
+                    .subcommand(
+                        SubCommand::with_name("run")
+                                .version("0.1")
+                                .author("Griffin Berlstein <griffin@berlste.in>")
+                                .about("Interpret a bril program")
+                                .setting(AppSettings::AllowNegativeNumbers)
+                                .arg(Arg::with_name("profile")
+                                     .short("p")
+                                     .long("profile")
+                                     .help("Report the number of dynamic instructions executed")
+                                )
+                                .arg(Arg::with_name("args")
+                                     .index(1)
+                                     .multiple(true)
+                                     .help("Arguments to main")
+                                )
+                    )
+                    .subcommand(
+                        SubCommand::with_name("cfg")
+                                .version("0.1")
+                                .author("Griffin Berlstein <griffin@berlste.in>")
+                                .about("Print the control-flow graph of each function in DOT format")
+                                .arg(Arg::with_name("annotate")
+                                     .long("annotate")
+                                     .takes_value(true)
+                                     .possible_values(formats::dot::ANNOTATIONS)
+                                     .help("Show the input and output sets of an analysis on each block")
+                                )
+                    )
+                    .subcommand(
+                        SubCommand::with_name("print")
+                                .version("0.1")
+                                .author("Griffin Berlstein <griffin@berlste.in>")
+                                .about("Print a bril program, as text unless another output format is given")
+                    )
                     .get_matches();
 
 
@@ -136,21 +165,69 @@ fn main() {
 
     match matches.subcommand() {
         ("transform", Some(sub_m)) => {
-            let optimizations = sub_m.values_of("optimizations");
+            let mut params = config::PassParams::default();
+            if let Some(factor) = sub_m.value_of("unroll-factor") {
+                params.unroll_factor = factor.parse().unwrap();
+            }
+            if let Some(count) = sub_m.value_of("peel-count") {
+                params.peel_count = count.parse().unwrap();
+            }
+            if let Some(budget) = sub_m.value_of("unswitch-budget") {
+                params.unswitch_budget = budget.parse().unwrap();
+            }
+            let pipeline = config::build_pipeline(sub_m.value_of("opt-level"),
+                                                  sub_m.values_of("optimizations").into_iter().flatten(),
+                                                  &params);
+            let input_format = sub_m.value_of("input-format");
+            let output_format = sub_m.value_of("output-format").unwrap_or("json");
 
             // If there are no optimizations just return what was given
-            if optimizations.is_none() {
+            if pipeline.is_empty() && input_format != Some("text") && output_format == "json" {
                 println!("{}", buffer);
                 exit(0)
             }
 
-            let confs = ConfigOptions::new(optimizations.unwrap());
-
-            let mut prog: Program = serde_json::from_str(&buffer).unwrap();
+            let prog = read_program(&buffer, input_format);
+            emit_program(pipeline.run(prog), output_format);
+            }
+        ("run", Some(sub_m)) => {
+            let prog = read_program(&buffer, sub_m.value_of("input-format"));
+            let args: Vec<String> = sub_m.values_of("args")
+                                         .map_or(Vec::new(), |x| x.map(String::from).collect());
+
+            let stdout = io::stdout();
+            match interpreter::run_program(&prog, &args, stdout.lock()) {
+                Ok(count) => {
+                    if sub_m.is_present("profile") {
+                        eprintln!("total_dyn_inst: {}", count);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    exit(2)
+                }
+            }
+        }
+        ("print", Some(sub_m)) => {
+            let prog = read_program(&buffer, sub_m.value_of("input-format"));
+            emit_program(prog, sub_m.value_of("output-format").unwrap_or("text"));
+        }
+        ("cfg", Some(sub_m)) => {
+            let prog = read_program(&buffer, sub_m.value_of("input-format"));
+            let cfg = prog.determine_cfg();
 
-            prog = apply_transformations(prog, confs);
-            println!("{}", serde_json::to_string_pretty(&prog).ok().unwrap_or_default());
+            let mut out = String::new();
+            for cfg_fun in cfg.functions.iter() {
+                let facts = match sub_m.value_of("annotate") {
+                    Some("reaching_defns") => Some(cfg_fun.reaching_defn_facts(&cfg.names)),
+                    Some("live") => Some(cfg_fun.live_var_facts(&cfg.names)),
+                    Some("cprop") => Some(cfg_fun.cprop_facts(&cfg.names)),
+                    _ => None
+                };
+                formats::dot::write_cfg_dot(&mut out, cfg_fun, &cfg.names, facts.as_deref()).unwrap();
             }
+            print!("{}", out);
+        }
         ("analyze", Some(sub_m)) => {
             let func = match sub_m.value_of("analysis") {
                 Some("reaching_defns") => {
@@ -159,16 +236,49 @@ fn main() {
                 Some("live") => {
                     CFGFunction::live_vars
                 }
+                Some("dom") => {
+                    CFGFunction::dominators
+                }
+                Some("idom") => {
+                    CFGFunction::immediate_dominators
+                }
+                Some("dom_tree") => {
+                    CFGFunction::dominator_tree
+                }
+                Some("frontier") => {
+                    CFGFunction::dominance_frontiers
+                }
+                Some("pdom") => {
+                    CFGFunction::post_dominators
+                }
+                Some("ipdom") => {
+                    CFGFunction::immediate_post_dominators
+                }
+                Some("pdom_tree") => {
+                    CFGFunction::post_dominator_tree
+                }
+                Some("cdg") => {
+                    CFGFunction::control_dependence
+                }
+                Some("loops") => {
+                    CFGFunction::loops
+                }
+                Some("induction") => {
+                    CFGFunction::inductions
+                }
+                Some("cprop") => {
+                    CFGFunction::constants
+                }
                 _ => {exit(1)}
             };
 
-            let prog: Program = serde_json::from_str(&buffer).unwrap();
+            let prog = read_program(&buffer, sub_m.value_of("input-format"));
 
             let cfg = prog.determine_cfg();
 
 
             for cfg_fun in cfg.functions.iter(){
-                func(cfg_fun)
+                func(cfg_fun, &cfg.names)
             }
 
         }
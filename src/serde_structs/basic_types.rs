@@ -1,5 +1,4 @@
 use std::fmt::{self,Display};
-use std::ops::{Add, Mul, Div, Sub, BitAnd, BitOr, Not};
 
 use serde::{self, Deserialize, Serialize};
 
@@ -38,107 +37,21 @@ impl Display for Literal {
         match &self {
             Literal::Int(x) => {write!(f, "{}", x)}
             Literal::Bool(x) => {write!(f, "{}", x)}
-            Literal::Float(x) => {write!(f, "{}", x)}
+            // match python's float formatting, which is what bril2txt uses
+            Literal::Float(x) if x.is_nan() => {write!(f, "nan")}
+            Literal::Float(x) => {write!(f, "{:?}", x)}
         }
     }
 }
 
-impl Add for Literal {
-    type Output = Literal;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        if let Literal::Int(v1) = self {
-            if let Literal::Int(v2) = rhs {
-                return Literal::Int(v1 + v2)
-            }
-        }
-        panic!("Type mismatch, cannot add {:?} + {:?}", self, rhs);
-}
-}
-
-impl Mul for Literal {
-    type Output = Literal;
-
-    fn mul(self, rhs: Self) -> Self::Output {
-        if let Literal::Int(v1) = self {
-            if let Literal::Int(v2) = rhs {
-                return Literal::Int(v1 * v2)
-            }
-        }
-        panic!("Type mismatch, cannot mul {:?} * {:?}", self, rhs);
-    }
-}
-
-impl Div for Literal {
-    type Output = Literal;
-
-    fn div(self, rhs: Self) -> Self::Output {
-        if let Literal::Int(v1) = self {
-            if let Literal::Int(v2) = rhs {
-                return Literal::Int(v1 / v2)
-            }
-        }
-        panic!("Type mismatch, cannot div {:?} / {:?}", self, rhs);
-    }
-}
-
-impl Sub for Literal {
-    type Output = Literal;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        if let Literal::Int(v1) = self {
-            if let Literal::Int(v2) = rhs {
-                return Literal::Int(v1 - v2)
-            }
-        }
-        panic!("Type mismatch, cannot sub {:?} - {:?}", self, rhs);
-    }
-}
-
-impl BitAnd for Literal {
-    type Output = Literal;
-
-    fn bitand(self, rhs: Self) -> Self::Output {
-        if let Literal::Bool(v1) = self {
-            if let Literal::Bool(v2) = rhs {
-                return Literal::Bool(v1 && v2)
-            }
-        }
-        panic!("Type mismatch, cannot and {:?} && {:?}", self, rhs);
-    }
-}
-
-impl BitOr for Literal {
-    type Output = Literal;
-
-    fn bitor(self, rhs: Self) -> Self::Output {
-        if let Literal::Bool(v1) = self {
-            if let Literal::Bool(v2) = rhs {
-                return Literal::Bool(v1 || v2)
-            }
-        }
-        panic!("Type mismatch, cannot or {:?} || {:?}", self, rhs);
-    }
-}
-
-impl Not for Literal {
-    type Output = Literal;
-
-    fn not(self) -> Self::Output {
-        if let Literal::Bool(v1) = self {
-                return Literal::Bool(!v1)
-
-        }
-        panic!("Type mismatch, cannot not !{:?}", self);
-    }
-}
-
 impl PartialEq for Literal {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Literal::Int(v1), Literal::Int(v2)) => v1 == v2,
             (Literal::Bool(v1), Literal::Bool(v2)) => v1 == v2,
-            (Literal::Float(v1), Literal::Float(v2)) => v1 == v2,
+            // Compared by their bits, so that 0.0 and -0.0 stay apart and a
+            // NaN is the same as itself
+            (Literal::Float(v1), Literal::Float(v2)) => v1.to_bits() == v2.to_bits(),
             _ => false
         }
     }
@@ -1,21 +1,30 @@
-use std::fmt::{self, Display};
 use serde::{self, Deserialize, Serialize};
-use super::names::{FnName, namer, Var};
-use super::basic_types::{Type, Literal};
+use std::collections::HashMap;
+use super::names::{FnName, Var, Interner};
+use super::basic_types::Type;
 use super::instructions::Instr;
-use super::operations::Op;
-use super::super::transformers::cfg::Node;
-use super::super::transformers::cfg::{connect_basic_blocks, construct_basic_blocks, construct_cfg_nodes};
+use super::super::transformers::cfg::{BlockId, Cfg};
+use super::super::transformers::dominance::{DominanceTree, PostDominanceTree, ControlDependenceGraph};
 use super::super::transformers::orphan::remove_inaccessible_blocks;
-use super::super::transformers::dce::{trivial_global_dce,local_dce};
-use super::super::transformers::lvn::run_lvn;
+use super::super::transformers::dce::{trivial_global_dce,local_dce,live_dce};
+use super::super::transformers::lvn::{run_lvn, run_superlocal_lvn};
+use super::super::transformers::cprop::propagate_constants;
+use super::super::transformers::sccp::sparse_conditional_constant_propagation;
+use super::super::transformers::gvn::global_value_numbering;
+use super::super::transformers::copyprop::propagate_copies;
 use super::super::transformers::ssa::{to_ssa, from_ssa};
+use super::super::transformers::loop_simplify::loop_simplify;
+use super::super::transformers::licm::loop_invariant_code_motion;
+use super::super::transformers::rotate::rotate_loops;
+use super::super::transformers::strength::strength_reduce;
+use super::super::transformers::unroll::{unroll_loops, peel_loops};
+use super::super::transformers::unswitch::unswitch_loops;
+use super::super::transformers::loop_deletion::delete_loops;
+
+use crate::analysis::{self, BlockFacts, LoopForest};
+use crate::analysis::reaching_defns::{VarDef, argument_definitions};
+use crate::analysis::induction::{induction_variables, Inductions, Stride};
 
-use std::rc::Rc;
-use crate::analysis;
-use crate::analysis::reaching_defns::VarDef;
-
-use std::mem::replace;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FnHeaders {
     pub name: Var,
@@ -23,19 +32,6 @@ pub struct FnHeaders {
     pub r_type: Type,
 }
 
-impl FnHeaders {
-    // Only should be used for dataflow analysis
-    pub fn generate_dummy_instrs(&self) -> Instr {
-        Instr::Const { op: Op::Const, dest: self.name, r_type: self.r_type.clone(),
-                value: match self.r_type {
-                    Type::Int => {Literal::Int(0)}
-                    Type::Bool => {Literal::Bool(false)}
-                    Type::Ptr(_) => {todo!()}
-                    Type::Float => {Literal::Float(0.0)}
-                }}
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Function {
     name: FnName,
@@ -50,22 +46,41 @@ pub struct Function {
 }
 
 impl Function {
+    pub fn new(name: FnName, args: Vec<FnHeaders>, r_type: Option<Type>, instrs: Vec<Instr>) -> Self {
+        Function {
+            name,
+            args,
+            r_type,
+            instrs
+        }
+    }
 
-    pub fn g_tcde(&mut self) {
-        trivial_global_dce(&mut self.instrs)
+    pub fn name(&self) -> FnName {
+        self.name
     }
 
-    pub fn make_cfg(self) -> CFGFunction {
+    pub fn args(&self) -> &[FnHeaders] {
+        &self.args
+    }
 
-        let mut blocks = construct_cfg_nodes(construct_basic_blocks(self.instrs));
+    pub fn return_type(&self) -> Option<&Type> {
+        self.r_type.as_ref()
+    }
 
-        connect_basic_blocks(&mut blocks);
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
+    }
 
+    pub fn g_tcde(&mut self) {
+        trivial_global_dce(&mut self.instrs)
+    }
+
+    pub fn make_cfg(self, names: &mut Interner) -> CFGFunction {
         CFGFunction {
             name: self.name,
             args: self.args,
             r_type: self.r_type,
-            blocks
+            cfg: Cfg::new(self.instrs, names)
         }
     }
 }
@@ -77,7 +92,7 @@ pub struct CFGFunction {
 
     r_type: Option<Type>,
 
-    blocks: Vec<Rc<Node>>,
+    cfg: Cfg,
 }
 
 impl CFGFunction {
@@ -86,120 +101,328 @@ impl CFGFunction {
             name: self.name,
             args: self.args,
             r_type: self.r_type,
-            instrs: self.blocks.into_iter().map(|x| Rc::try_unwrap(x).unwrap().make_serializeable()).flatten().collect()
+            instrs: self.cfg.into_instrs()
         }
     }
 
-    pub fn drop_orphan_blocks(&mut self) {
-        let tmp = replace(&mut self.blocks, Vec::new());
+    pub fn name(&self) -> FnName {
+        self.name
+    }
 
-        self.blocks = remove_inaccessible_blocks(tmp);
+    pub fn cfg(&self) -> &Cfg {
+        &self.cfg
+    }
 
-        for block in self.blocks.iter() {
-            block.prune_missing_predecessors()
-        }
+    pub fn drop_orphan_blocks(&mut self) {
+        remove_inaccessible_blocks(&mut self.cfg)
     }
 
     pub fn apply_basic_dce(&mut self) {
-        for block in self.blocks.iter_mut() {
-            local_dce(block);
+        for node in self.cfg.nodes_mut() {
+            local_dce(&mut node.contents);
         }
     }
-    pub fn apply_lvn(&mut self) {
-        for blk in self.blocks.iter() {
-            let node = blk.as_ref();
-            let contents = &mut node.contents.borrow_mut().0;
-            run_lvn(contents)
-        }
+    pub fn apply_live_dce(&mut self) {
+        live_dce(&mut self.cfg)
     }
 
-    pub fn reaching_defns(&self) {
-        let analysis_nodes = analysis::reaching_definitions(&self.blocks, &self.args);
+    pub fn apply_lvn(&mut self, names: &mut Interner) {
+        for node in self.cfg.nodes_mut() {
+            run_lvn(&mut node.contents.0, names)
+        }
+    }
 
-        println!("\n\nRunning reaching definitions analysis on {}\n", self.name);
-        for (index, node) in analysis_nodes.into_iter().enumerate() {
-            let mut out_vars = node.out_data_as_vec();
-            out_vars.sort_by(|x: &VarDef, y: &VarDef|  {
-                (x.0, x.1).cmp(&(y.0, y.1))
-            });
+    pub fn apply_superlocal_lvn(&mut self, names: &mut Interner) {
+        run_superlocal_lvn(&mut self.cfg, names)
+    }
 
-            let mut in_vars = node.in_data_as_vec();
-            in_vars.sort_by(|x: &VarDef, y: &VarDef|  {
+    /// The sorted input and output sets of reaching definitions for each
+    /// block, in layout order
+    pub fn reaching_defn_facts(&self, names: &Interner) -> Vec<BlockFacts> {
+        let describe = |mut vars: Vec<VarDef>| -> Vec<String> {
+            vars.sort_by(|x: &VarDef, y: &VarDef|  {
                 (x.0, x.1).cmp(&(y.0, y.1))
             });
-            if index == 0 {
-                println!("Function start:");
-                print!(" Input:");
+            vars.iter().map(|x| x.describe(names)).collect()
+        };
 
-                for var in out_vars  {
-                    print!(" {}", var);
-                }
-                println!("\n")
-            } else {
-                println!("Block {} [{}]", index, self.blocks[index - 1].contents.borrow());
-                print!(" Input:");
-                for var in in_vars {
-                    print!(" {}", var);
-                }
-                print!("\n Output:");
-                for var in out_vars {
-                    print!(" {}", var);
-                }
-                println!("\n")
+        analysis::reaching_definitions(&self.cfg, &self.args).into_iter().map(|node| {
+            BlockFacts {
+                input: describe(node.in_data_as_vec()),
+                output: describe(node.out_data_as_vec()),
             }
-
-
-        }
+        }).collect()
     }
 
-    pub fn live_vars(&self) {
-        let analysis_nodes = analysis::live_variables(&self.blocks);
-
-        println!("\n\nRunning live variable analysis on {}\n", self.name);
-        for (index, node) in analysis_nodes.into_iter().enumerate() {
-
-            let mut out_vars = node.out_data_as_vec();
-            out_vars.sort_by(|x: &Var, y: &Var|  {
-                x.cmp(&y)
-            });
+    /// The sorted live-in and live-out sets for each block, in layout order
+    pub fn live_var_facts(&self, names: &Interner) -> Vec<BlockFacts> {
+        let describe = |mut vars: Vec<Var>| -> Vec<String> {
+            vars.sort();
+            vars.iter().map(|x| names.resolve(*x).to_string()).collect()
+        };
+
+        analysis::live_variables(&self.cfg).into_iter().map(|node| {
+            BlockFacts {
+                input: describe(node.in_data_as_vec()),
+                output: describe(node.out_data_as_vec()),
+            }
+        }).collect()
+    }
 
-            let mut in_vars = node.in_data_as_vec();
-            in_vars.sort_by(|x: &Var, y: &Var|  {
-                x.cmp(&y)
-            });
-            println!("Block {} [{}]", index, self.blocks[index].contents.borrow());
+    fn print_facts(&self, names: &Interner, facts: Vec<BlockFacts>, first_block: usize) {
+        for (id, facts) in self.cfg.ids().zip(facts) {
+            println!("Block {} [{}]", id.index() + first_block, self.cfg[id].contents.describe(names));
             print!(" Input:");
-            for var in in_vars {
+            for var in facts.input {
                 print!(" {}", var);
             }
             print!("\n Output:");
-            for var in out_vars {
+            for var in facts.output {
                 print!(" {}", var);
             }
             println!("\n")
         }
     }
 
-    pub fn to_ssa(&mut self) {
-        to_ssa(&mut self.blocks, &self.args[..])
+    pub fn reaching_defns(&self, names: &Interner) {
+        println!("\n\nRunning reaching definitions analysis on {}\n", names.resolve(self.name));
+
+        let mut start_vars: Vec<VarDef> = argument_definitions(&self.args).into_iter().collect();
+        start_vars.sort_by(|x: &VarDef, y: &VarDef|  {
+            (x.0, x.1).cmp(&(y.0, y.1))
+        });
+        println!("Function start:");
+        print!(" Input:");
+        for var in start_vars {
+            print!(" {}", var.describe(names));
+        }
+        println!("\n");
+
+        // Block numbering starts at 1 since 0 stands for the arguments
+        self.print_facts(names, self.reaching_defn_facts(names), 1)
     }
 
-    pub fn from_ssa(&mut self) {
-        from_ssa(&mut self.blocks)
+    pub fn live_vars(&self, names: &Interner) {
+        println!("\n\nRunning live variable analysis on {}\n", names.resolve(self.name));
+        self.print_facts(names, self.live_var_facts(names), 0)
+    }
+
+    /// The known constants at the start and end of each block, in layout
+    /// order, with `?` for variables that could hold more than one value
+    pub fn cprop_facts(&self, names: &Interner) -> Vec<BlockFacts> {
+        let describe = |data: &HashMap<Var, analysis::cprop::Value>| -> Vec<String> {
+            let mut vars: Vec<&Var> = data.keys().collect();
+            vars.sort();
+            vars.iter().map(|x| format!("{}={}", names.resolve(**x), data[x].describe())).collect()
+        };
+
+        analysis::constant_propagation(&self.cfg, &self.args).into_iter().map(|node| {
+            BlockFacts {
+                input: describe(&node.in_data),
+                output: describe(&node.out_data),
+            }
+        }).collect()
+    }
+
+    pub fn constants(&self, names: &Interner) {
+        println!("\n\nRunning constant propagation on {}\n", names.resolve(self.name));
+        self.print_facts(names, self.cprop_facts(names), 0)
+    }
+
+    pub fn dominance_tree(&self) -> DominanceTree {
+        DominanceTree::new(&self.cfg)
+    }
+
+    pub fn post_dominance_tree(&self) -> PostDominanceTree {
+        PostDominanceTree::new(&self.cfg)
+    }
+
+    pub fn control_dependence_graph(&self) -> ControlDependenceGraph {
+        ControlDependenceGraph::new(&self.cfg)
+    }
+
+    pub fn loop_forest(&self) -> LoopForest {
+        LoopForest::new(&self.cfg, &self.dominance_tree())
+    }
+
+    /// The induction variables of each loop, in the same order as the loops
+    /// of `loop_forest`
+    pub fn induction_variables(&self) -> Vec<Inductions> {
+        self.loop_forest().loops().iter().map(|x| induction_variables(&self.cfg, x)).collect()
     }
-}
 
-impl Display for CFGFunction {
-    fn fmt(&self, f: & mut fmt::Formatter<'_>) -> fmt::Result {
-        let namer = namer();
-        writeln!(f, "== Function: {} ==", namer.get_string(&self.name.0))?;
-        writeln!(f, "args: {:?}", &self.args)?;
-        if let Some(x) = &self.r_type {
-            writeln!(f, "returns {}", x)?;
+    fn print_dominance<T, F>(&self, names: &Interner, title: &str, tree: T, describe: F)
+        where F: Fn(&T, BlockId) -> String {
+        println!("\n\nRunning {} analysis on {}\n", title, names.resolve(self.name));
+        for id in self.cfg.ids() {
+            println!("Block {} [{}]", id.index(), self.cfg[id].contents.describe(names));
+            println!(" {}\n", describe(&tree, id));
         }
-        for node in self.blocks.iter() {
-            writeln!(f, "\n{}", node)?;
+    }
+
+    pub fn dominators(&self, names: &Interner) {
+        self.print_dominance(names, "dominator", self.dominance_tree(), |tree, id| {
+            format!("Dominators:{}", block_list(tree.dominators(id)))
+        })
+    }
+
+    pub fn immediate_dominators(&self, names: &Interner) {
+        self.print_dominance(names, "immediate dominator", self.dominance_tree(), |tree, id| {
+            format!("Immediate dominator:{}", block_list(tree.idom(id)))
+        })
+    }
+
+    pub fn dominator_tree(&self, names: &Interner) {
+        self.print_dominance(names, "dominator tree", self.dominance_tree(), |tree, id| {
+            format!("Children:{}", block_list(tree.children(id)))
+        })
+    }
+
+    pub fn dominance_frontiers(&self, names: &Interner) {
+        self.print_dominance(names, "dominance frontier", self.dominance_tree(), |tree, id| {
+            format!("Frontier:{}", block_list(tree.frontier(id)))
+        })
+    }
+
+    pub fn post_dominators(&self, names: &Interner) {
+        self.print_dominance(names, "post-dominator", self.post_dominance_tree(), |tree, id| {
+            format!("Post-dominators:{}", block_list(tree.post_dominators(id)))
+        })
+    }
+
+    pub fn immediate_post_dominators(&self, names: &Interner) {
+        self.print_dominance(names, "immediate post-dominator", self.post_dominance_tree(), |tree, id| {
+            let ipdom = match tree.ipdom(id) {
+                Some(ipdom) => format!(" {}", ipdom.index()),
+                None if tree.reaches_exit(id) => " exit".to_string(),
+                None => String::new(),
+            };
+            format!("Immediate post-dominator:{}", ipdom)
+        })
+    }
+
+    pub fn post_dominator_tree(&self, names: &Interner) {
+        let tree = self.post_dominance_tree();
+        println!("\n\nRunning post-dominator tree analysis on {}\n", names.resolve(self.name));
+        println!("Exit\n Children:{}\n", block_list(tree.exit_children()));
+        for id in self.cfg.ids() {
+            println!("Block {} [{}]", id.index(), self.cfg[id].contents.describe(names));
+            println!(" Children:{}\n", block_list(tree.children(id)));
         }
-        Ok(())
     }
+
+    pub fn control_dependence(&self, names: &Interner) {
+        self.print_dominance(names, "control dependence", self.control_dependence_graph(), |cdg, id| {
+            format!("Depends on:{}\n Controls:{}",
+                    block_list(cdg.controllers(id).iter().copied()),
+                    block_list(cdg.dependents(id).iter().copied()))
+        })
+    }
+
+    pub fn loops(&self, names: &Interner) {
+        println!("\n\nRunning loop analysis on {}\n", names.resolve(self.name));
+        let forest = self.loop_forest();
+        let loop_list = |idxs: &[usize]| -> String {
+            idxs.iter().map(|x| format!(" {}", x)).collect()
+        };
+        for (idx, natural_loop) in forest.loops().iter().enumerate() {
+            println!("Loop {} [{}]", idx, self.cfg[natural_loop.header].contents.describe(names));
+            println!(" Header: {}", natural_loop.header.index());
+            println!(" Latches:{}", block_list(natural_loop.latches.iter().copied()));
+            println!(" Body:{}", block_list(natural_loop.body.iter().copied()));
+            println!(" Exiting:{}", block_list(natural_loop.exiting.iter().copied()));
+            println!(" Exits:{}", block_list(natural_loop.exits.iter().copied()));
+            println!(" Depth: {}", natural_loop.depth);
+            println!(" Parent:{}", loop_list(&natural_loop.parent.into_iter().collect::<Vec<_>>()));
+            println!(" Children:{}\n", loop_list(&natural_loop.children));
+        }
+    }
+
+    pub fn inductions(&self, names: &Interner) {
+        println!("\n\nRunning induction variable analysis on {}\n", names.resolve(self.name));
+        let forest = self.loop_forest();
+        for (idx, (natural_loop, inductions)) in forest.loops().iter().zip(self.induction_variables()).enumerate() {
+            println!("Loop {} [{}]", idx, self.cfg[natural_loop.header].contents.describe(names));
+            for basic in inductions.basic.iter() {
+                let sign = if basic.decreasing { "-" } else { "" };
+                println!(" {}: basic, step {}{}", names.resolve(basic.var), sign, names.resolve(basic.step));
+            }
+            for derived in inductions.derived.iter() {
+                print!(" {}: derived from {}", names.resolve(derived.var), names.resolve(derived.basic));
+                if let Some(from) = derived.from {
+                    print!(" through {}", names.resolve(from));
+                }
+                if let Stride::Scaled(scale) = derived.stride {
+                    print!(", stride {}", names.resolve(scale));
+                }
+                if derived.pointer {
+                    print!(", pointer");
+                }
+                println!();
+            }
+            println!();
+        }
+    }
+
+    pub fn to_ssa(&mut self, names: &mut Interner) {
+        to_ssa(&mut self.cfg, &self.args[..], names)
+    }
+
+    pub fn apply_cprop(&mut self) {
+        propagate_constants(&mut self.cfg, &self.args)
+    }
+
+    pub fn apply_copyprop(&mut self) {
+        propagate_copies(&mut self.cfg)
+    }
+
+    pub fn apply_sccp(&mut self) {
+        sparse_conditional_constant_propagation(&mut self.cfg, &self.args)
+    }
+
+    pub fn apply_gvn(&mut self) {
+        global_value_numbering(&mut self.cfg, &self.args)
+    }
+
+    pub fn apply_loop_simplify(&mut self, names: &mut Interner) {
+        loop_simplify(&mut self.cfg, names)
+    }
+
+    pub fn apply_rotate(&mut self, names: &mut Interner) {
+        rotate_loops(&mut self.cfg, names)
+    }
+
+    pub fn apply_licm(&mut self, names: &mut Interner) {
+        loop_invariant_code_motion(&mut self.cfg, &self.args, names)
+    }
+
+    pub fn apply_strength_reduction(&mut self, names: &mut Interner) {
+        strength_reduce(&mut self.cfg, &self.args, names)
+    }
+
+    pub fn apply_unroll(&mut self, factor: usize, names: &mut Interner) {
+        unroll_loops(&mut self.cfg, &self.args, factor, names)
+    }
+
+    pub fn apply_unswitch(&mut self, budget: usize, names: &mut Interner) {
+        unswitch_loops(&mut self.cfg, &self.args, budget, names)
+    }
+
+    pub fn apply_loop_deletion(&mut self, names: &mut Interner) {
+        delete_loops(&mut self.cfg, names)
+    }
+
+    pub fn apply_peel(&mut self, count: usize, names: &mut Interner) {
+        peel_loops(&mut self.cfg, count, names)
+    }
+
+    pub fn from_ssa(&mut self, names: &mut Interner) {
+        from_ssa(&mut self.cfg, names)
+    }
+}
+
+// Block numbers separated and preceded by spaces, matching how the other
+// analyses list their results
+fn block_list<I: IntoIterator<Item = BlockId>>(ids: I) -> String {
+    ids.into_iter().map(|x| format!(" {}", x.index())).collect()
 }
@@ -1,11 +1,10 @@
-use std::fmt::{self, Display};
 use serde::{self, Deserialize, Serialize};
 
-use super::names::{FnName, Label, namer, Var};
+use super::names::{FnName, Label, Var};
 use super::basic_types::{Literal, Type};
 use super::operations::Op;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Instr {
     #[serde(rename = "label")]
@@ -66,6 +65,14 @@ impl Instr {
         }
     }
 
+    /// The variable this instruction defines, if any
+    pub fn dest(&self) -> Option<Var> {
+        match self {
+            Instr::Const { dest, .. } | Instr::Value { dest, .. } => Some(*dest),
+            _ => None,
+        }
+    }
+
     pub fn extract_label(&self) -> Option<Label> {
         if let Instr::Label {label} = &self {
             Some(*label)
@@ -81,46 +88,3 @@ impl Instr {
 //     }
 // }
 
-impl Display for Instr {
-    fn fmt(&self, f: & mut fmt::Formatter<'_>) -> fmt::Result {
-        let namer = namer();
-        match &self {
-            Instr::Label { label } => {
-                write!(f, ".{}", namer.get_string(&label.0))
-            }
-            Instr::Const { dest, r_type, value, .. } => {
-                write!(f, "{}: {} = const {}", namer.get_string(&dest.0), r_type, value)
-            }
-            Instr::Value { op, dest, r_type, args, funcs, labels } => {
-                write!(f, "{}: {} = {}", namer.get_string(&dest.0), r_type, op)?;
-                for func in funcs.iter(){
-                    write!(f, " {}", namer.get_string(&func.0))?
-                }
-
-                for arg in args.iter() {
-                    write!(f," {}", namer.get_string(&arg.0))?
-                }
-
-                for label in labels.iter() {
-                    write!(f, " {}", namer.get_string(&label.0))?
-                }
-                Ok(())
-            }
-            Instr::Effect { op, args, funcs, labels } => {
-                write!(f, "{}", op)?;
-                for func in funcs.iter(){
-                    write!(f, " {}", namer.get_string(&func.0))?;
-                }
-
-                for arg in args.iter() {
-                    write!(f," {}", namer.get_string(&arg.0))?;
-                }
-
-                for label in labels.iter() {
-                    write!(f, " {}", namer.get_string(&label.0))?;
-                }
-                Ok(())
-            }
-        }
-    }
-}
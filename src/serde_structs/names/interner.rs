@@ -0,0 +1,118 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use serde::de::{self, Deserializer, Deserialize, Visitor};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use super::wrapper_names::Label;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Ord, PartialOrd)]
+pub struct Name(pub u64);
+
+/// Owns the strings behind every `Name` of a program. Each program carries
+/// its own interner, so fresh names are only unique within that program and
+/// names from different programs must never be mixed.
+#[derive(Debug, Default, Clone)]
+pub struct Interner {
+    strings: Vec<String>,
+    names: HashMap<String, Name>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    pub fn intern(&mut self, key: &str) -> Name {
+        if let Some(name) = self.names.get(key) {
+            return *name;
+        }
+        let name = Name(self.strings.len() as u64);
+        self.strings.push(key.to_string());
+        self.names.insert(key.to_string(), name);
+        name
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<Name> {
+        self.names.get(key).copied()
+    }
+
+    pub fn resolve<N: Into<Name>>(&self, name: N) -> &str {
+        &self.strings[name.into().0 as usize]
+    }
+
+    /// A name of the form `base_n` that is not yet in use
+    pub fn fresh<N: Into<Name>>(&mut self, base: N) -> Name {
+        let base = self.resolve(base).to_string();
+        self.gen_fresh_name(&base)
+    }
+
+    pub fn fresh_label(&mut self) -> Label {
+        Label(self.gen_fresh_name("tmp_label"))
+    }
+
+    fn gen_fresh_name(&mut self, base: &str) -> Name {
+        let mut counter = 1;
+        let mut fresh = format!("{}_{}", base, counter);
+        while self.names.contains_key(&fresh) {
+            counter += 1;
+            fresh = format!("{}_{}", base, counter);
+        };
+
+        self.intern(&fresh)
+    }
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Interner>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `names` as the interner used to deserialize and serialize
+/// any `Name` on this thread, handing the interner back afterwards.
+pub fn with_names<T, F: FnOnce() -> T>(names: Interner, f: F) -> (T, Interner) {
+    let previous = CONTEXT.with(|ctx| ctx.replace(Some(names)));
+    let result = f();
+    let names = CONTEXT.with(|ctx| ctx.replace(previous)).unwrap();
+    (result, names)
+}
+
+fn in_context<T, F: FnOnce(&mut Interner) -> T>(f: F) -> T {
+    CONTEXT.with(|ctx| {
+        let mut ctx = ctx.borrow_mut();
+        f(ctx.as_mut().expect("names used by serde outside of `with_names`"))
+    })
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+
+            struct NameVisitor;
+
+            impl<'de> Visitor<'de> for NameVisitor {
+                type Value = Name;
+
+                fn visit_str<E>(self, value: &str) -> Result<Name,E>
+                where
+                    E: de::Error
+                {
+                    Ok(in_context(|names| names.intern(value)))
+                }
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("`name`")
+                }
+    }
+    deserializer.deserialize_identifier(NameVisitor)
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        in_context(|names| serializer.serialize_str(names.resolve(*self)))
+    }
+}
@@ -1,5 +1,5 @@
-mod name_mapper;
+mod interner;
 mod wrapper_names;
 
-pub use name_mapper::namer;
+pub use interner::{Interner, with_names};
 pub use wrapper_names::*;
@@ -1,50 +1,29 @@
 use serde::{self, Deserialize, Serialize};
-use super::name_mapper::{namer,Name};
-use std::fmt::{Display, Debug};
+use super::interner::Name;
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Debug)]
 pub struct Var(pub Name);
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Debug)]
 pub struct Label(pub Name);
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Debug)]
 pub struct FnName(pub Name);
 
-
-impl Debug for Var {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<Var: {} [{}]>", namer().get_string(&self.0), &(self.0).0)
-    }
-}
-
-
-impl Debug for Label {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<Label: {} [{}]>", namer().get_string(&self.0), &self.0)
-    }
-}
-
-impl Debug for FnName {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<FnName: {} [{}]>", namer().get_string(&self.0), &self.0)
-    }
-}
-
-impl Display for Var {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+impl From<Var> for Name {
+    fn from(var: Var) -> Name {
+        var.0
     }
 }
 
-impl Display for Label {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+impl From<Label> for Name {
+    fn from(label: Label) -> Name {
+        label.0
     }
 }
 
-impl Display for FnName {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+impl From<FnName> for Name {
+    fn from(name: FnName) -> Name {
+        name.0
     }
 }
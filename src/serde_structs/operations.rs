@@ -1,4 +1,5 @@
 use std::fmt::{self, Display};
+use std::str::FromStr;
 use serde::{self, Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
@@ -65,6 +66,45 @@ impl Op {
             _ => false
         }
     }
+
+    /// Whether the result depends only on the arguments, with nothing else
+    /// observable happening. Note that `div` is pure but can still fail.
+    pub fn is_pure(&self) -> bool {
+        matches!(self,
+            Op::Const | Op::Add | Op::Mul | Op::Sub | Op::Div
+            | Op::Eq | Op::Lt | Op::Gt | Op::Le | Op::Ge
+            | Op::Not | Op::And | Op::Or | Op::Id | Op::PtrAdd
+            | Op::FAdd | Op::FMul | Op::FSub | Op::FDiv
+            | Op::FEq | Op::FLt | Op::FLe | Op::FGt | Op::FGe)
+    }
+
+    /// The comparison giving the same result with its arguments swapped
+    pub fn swapped(&self) -> Option<Op> {
+        match self {
+            Op::Lt => Some(Op::Gt),
+            Op::Le => Some(Op::Ge),
+            Op::Gt => Some(Op::Lt),
+            Op::Ge => Some(Op::Le),
+            Op::Eq => Some(Op::Eq),
+            Op::FLt => Some(Op::FGt),
+            Op::FLe => Some(Op::FGe),
+            Op::FGt => Some(Op::FLt),
+            Op::FGe => Some(Op::FLe),
+            Op::FEq => Some(Op::FEq),
+            _ => None,
+        }
+    }
+
+    /// The integer comparison that holds exactly when this one doesn't
+    pub fn negated(&self) -> Option<Op> {
+        match self {
+            Op::Lt => Some(Op::Ge),
+            Op::Le => Some(Op::Gt),
+            Op::Gt => Some(Op::Le),
+            Op::Ge => Some(Op::Lt),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Op {
@@ -74,3 +114,49 @@ impl Display for Op {
         write!(f, "{}", op)
     }
 }
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let op = match s {
+            "const" => Op::Const,
+            "add" => Op::Add,
+            "mul" => Op::Mul,
+            "sub" => Op::Sub,
+            "div" => Op::Div,
+            "eq" => Op::Eq,
+            "lt" => Op::Lt,
+            "gt" => Op::Gt,
+            "le" => Op::Le,
+            "ge" => Op::Ge,
+            "not" => Op::Not,
+            "and" => Op::And,
+            "or" => Op::Or,
+            "jmp" => Op::Jmp,
+            "br" => Op::Br,
+            "call" => Op::Call,
+            "ret" => Op::Ret,
+            "id" => Op::Id,
+            "print" => Op::Print,
+            "nop" => Op::Nop,
+            "alloc" => Op::Alloc,
+            "free" => Op::Free,
+            "store" => Op::Store,
+            "load" => Op::Load,
+            "ptradd" => Op::PtrAdd,
+            "fadd" => Op::FAdd,
+            "fmul" => Op::FMul,
+            "fsub" => Op::FSub,
+            "fdiv" => Op::FDiv,
+            "feq" => Op::FEq,
+            "flt" => Op::FLt,
+            "fle" => Op::FLe,
+            "fgt" => Op::FGt,
+            "fge" => Op::FGe,
+            "phi" => Op::Phi,
+            _ => return Err(format!("unknown operation `{}`", s)),
+        };
+        Ok(op)
+    }
+}
@@ -1,19 +1,45 @@
+use std::fmt::{self, Display};
 use serde::{self, Deserialize, Serialize};
 use super::functions::{CFGFunction, Function};
+use super::names::{Interner, with_names};
+use crate::formats::printer::write_program;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Program {
     pub functions: Vec<Function>,
+
+    #[serde(skip)]
+    pub names: Interner,
 }
 
 pub struct CFGProgram {
-    pub functions: Vec<CFGFunction>
+    pub functions: Vec<CFGFunction>,
+    pub names: Interner,
 }
 
 impl Program {
-    pub fn determine_cfg(self) -> CFGProgram {
+    pub fn new(functions: Vec<Function>, names: Interner) -> Self {
+        Program { functions, names }
+    }
+
+    pub fn from_json(input: &str) -> serde_json::Result<Program> {
+        let (prog, names) = with_names(Interner::new(), || serde_json::from_str::<Program>(input));
+        let mut prog = prog?;
+        prog.names = names;
+        Ok(prog)
+    }
+
+    pub fn into_json(mut self) -> serde_json::Result<String> {
+        let names = std::mem::take(&mut self.names);
+        with_names(names, || serde_json::to_string_pretty(&self)).0
+    }
+
+    pub fn determine_cfg(mut self) -> CFGProgram {
+        let names = &mut self.names;
+        let functions = self.functions.into_iter().map(|f| f.make_cfg(names)).collect();
         CFGProgram {
-            functions: self.functions.into_iter().map(|f| f.make_cfg()).collect()
+            functions,
+            names: self.names
         }
     }
 
@@ -22,8 +48,15 @@ impl Program {
 impl CFGProgram {
     pub fn make_serializeable(self) -> Program {
         Program {
-            functions: self.functions.into_iter().map(|f| f.make_serializeable()).collect()
+            functions: self.functions.into_iter().map(|f| f.make_serializeable()).collect(),
+            names: self.names
         }
     }
 
 }
+
+impl Display for Program {
+    fn fmt(&self, f: & mut fmt::Formatter<'_>) -> fmt::Result {
+        write_program(f, self)
+    }
+}
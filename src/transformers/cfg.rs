@@ -1,15 +1,8 @@
-use super::super::serde_structs::structs::{Label, Instr, Op, Var, Type};
-use super::super::serde_structs::namer;
-use std::collections::HashMap;
-use std::rc::{Rc, Weak};
-use std::{cell::RefCell, fmt, fmt::Display};
+use super::super::serde_structs::structs::{Label, Instr, Interner, Op, Var};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Index, IndexMut};
 use std::iter::Iterator;
 
-
-
-type LinkTarget = Weak<Node>;
-type LabelMap = HashMap<Label, Rc<Node>>;
-
 #[derive(Debug, Default)]
 pub struct Block(pub Vec<Instr>);
 
@@ -23,40 +16,40 @@ impl Block {
     }
 
     pub fn label(&self) -> Option<Label> {
-        self.0.get(0)?.extract_label()
+        self.0.first()?.extract_label()
     }
 
-    pub fn last(&self) -> &Instr {
-        self.0.last().unwrap()
+    pub fn last(&self) -> Option<&Instr> {
+        self.0.last()
     }
 
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
-    fn make_serializeable(self) -> Vec<Instr> {
-        self.0
-    }
-
-    fn iter(&self) -> std::slice::Iter<Instr> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Instr> {
         self.0.iter()
     }
 
-    fn iter_mut(&mut self) -> std::slice::IterMut<Instr> {
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Instr> {
         self.0.iter_mut()
     }
 
+    // The labels of a trailing jmp or br, if there is one
+    fn terminator_labels_mut(&mut self) -> Option<&mut Vec<Label>> {
+        match self.0.last_mut() {
+            Some(Instr::Effect { op: Op::Jmp, labels, .. })
+            | Some(Instr::Effect { op: Op::Br, labels, .. }) => Some(labels),
+            _ => None,
+        }
+    }
 }
 
-impl Display for Block {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Block {
+    pub fn describe<'a>(&self, names: &'a Interner) -> &'a str {
         match self.label() {
-            Some(l) => {
-                write!(f, "{}", l)
-            }
-            None => {
-                write!(f, "unlabeled")
-            }
+            Some(l) => names.resolve(l),
+            None => "unlabeled",
         }
     }
 }
@@ -71,586 +64,512 @@ impl IntoIterator for Block {
     }
 }
 
-#[derive(Debug)]
+/// Index of a node in its `Cfg`. Ids are only meaningful for the graph that
+/// produced them and are invalidated when blocks are removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(usize);
+
+impl BlockId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        BlockId(index)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Link {
     Ret,
     Exit,
-    Fallthrough(LinkTarget),
-    Jump(LinkTarget),
+    Fallthrough(BlockId),
+    Jump(BlockId),
     Branch {
-        true_branch: LinkTarget,
-        false_branch: LinkTarget,
+        true_branch: BlockId,
+        false_branch: BlockId,
     },
 }
 
+impl Link {
+    pub fn targets(&self) -> Vec<BlockId> {
+        match *self {
+            Link::Ret | Link::Exit => Vec::new(),
+            Link::Fallthrough(target) | Link::Jump(target) => vec![target],
+            Link::Branch { true_branch, false_branch } if true_branch == false_branch => {
+                vec![true_branch]
+            }
+            Link::Branch { true_branch, false_branch } => vec![true_branch, false_branch],
+        }
+    }
+
+    fn remap<F: Fn(BlockId) -> BlockId>(&mut self, f: F) {
+        match self {
+            Link::Ret | Link::Exit => {}
+            Link::Fallthrough(target) | Link::Jump(target) => *target = f(*target),
+            Link::Branch { true_branch, false_branch } => {
+                *true_branch = f(*true_branch);
+                *false_branch = f(*false_branch);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Node {
-    pub contents: RefCell<Block>,
-    pub out: RefCell<Option<Link>>,
-    pub predecessors: RefCell<Vec<Weak<Node>>>,
-    pub idx: RefCell<Option<usize>>, // this is bad and I am bad
+    pub contents: Block,
+    out: Link,
+    predecessors: Vec<BlockId>,
     label: Label
 }
 
 impl Node {
-    pub fn from_block(input: Block) -> Node {
+    fn from_block(input: Block, names: &mut Interner) -> Node {
         if input.is_empty() {
             panic!("Tried to create an empty block????\n")
         }
 
-        let label = input.label().unwrap_or(namer().fresh_label());
-            Node {
-                contents: RefCell::new(input),
-                out: RefCell::new(None),
-                predecessors: RefCell::new(Vec::new()),
-                idx: RefCell::new(None),
-                label
-            }
-    }
-
-    pub fn empty_block() -> Rc<Self> {
-        let mut new = Node {
-            contents: RefCell::<Block>::default(),
-            out: RefCell::<Option<Link>>::default(),
-            predecessors: RefCell::<Vec<Weak<Node>>>::default(),
-            idx: RefCell::<Option<usize>>::default(),
-            label: namer().fresh_label()
-        };
-        new.normalize();
-        Rc::new(new)
-    }
-
-    pub fn new(input: Vec<Instr>) -> Node{
-        Node::from_block(Block::new(input))
-    }
-
-    pub fn clear_predecessors(&self) {
-        self.predecessors.replace(Vec::new());
-    }
-
-    pub fn dummy_block(
-        contents: RefCell<Block>,
-        out: RefCell<Option<Link>>,
-        predecessors: RefCell<Vec<Weak<Node>>>,
-        idx: RefCell<Option<usize>>) -> Self {
-
+        let label = input.label().unwrap_or_else(|| names.fresh_label());
         Node {
-            contents,
-            out,
-            predecessors,
-            idx,
-            label: namer().fresh_label()
+            contents: input,
+            out: Link::Exit,
+            predecessors: Vec::new(),
+            label
         }
     }
 
-    pub fn label(&self) -> Label{
+    pub fn label(&self) -> Label {
         self.label
     }
 
-    pub fn predecessor_labels(&self) -> Vec<Label> {
-        self.predecessors.borrow().iter().map(|x| Weak::upgrade(x).unwrap().label()).collect()
-    }
-
-    pub fn successor_labels(&self) -> Vec<Label> {
-        self.successor_refs().iter().map(|x| x.label()).collect()
-    }
-
-    pub fn block_label(&self) -> Option<Label>{
-        self.contents.borrow().label()
+    pub fn block_label(&self) -> Option<Label> {
+        self.contents.label()
     }
 
     pub fn is_labeled(&self) -> bool {
-        self.contents.borrow().label().is_some()
+        self.contents.label().is_some()
     }
 
-    pub fn normalize(&self) {
+    pub fn normalize(&mut self) {
         if self.block_label().is_none() {
-            let block: &mut Block = &mut self.contents.borrow_mut();
-            block.0.insert(0, Instr::Label {label: self.label})
+            self.contents.0.insert(0, Instr::Label { label: self.label })
         }
     }
 
-    pub fn make_serializeable(self) -> Vec<Instr> {
-        self.contents.into_inner().make_serializeable()
+    pub fn link(&self) -> Link {
+        self.out
     }
 
-    pub fn successor_count(&self) -> usize {
-        let out: &Option<Link> = &self.out.borrow();
-        match out {
-            Some(x) => {
-                match x {
-                    Link::Branch {..} => 2,
-                    Link::Exit => 0,
-                    Link::Jump {..} => 1,
-                    Link::Fallthrough {..} => 1,
-                    Link::Ret => 0,
-                }
-            }
-            None => 0
-        }
+    pub fn successors(&self) -> Vec<BlockId> {
+        self.out.targets()
     }
 
-    pub fn successor_refs(&self) -> Vec<Rc<Node>> {
-        let successor: &Option<Link> = &self.out.borrow();
-
-        match successor {
-            Some(link) => {
-                match link {
-                    Link::Ret => Vec::new(),
-                    Link::Exit => Vec::new(),
-                    Link::Fallthrough(weak) => Weak::upgrade(weak).map_or(Vec::new(), |s| { vec! [s] }),
-                    Link::Jump(weak) => Weak::upgrade(weak).map_or(Vec::new(), |s| { vec! [s] }),
-                    Link::Branch { true_branch, false_branch } => {
-                        let mut true_br = Weak::upgrade(true_branch).map_or(Vec::new(), |s| { vec! [s] });
-                        let mut false_br  = Weak::upgrade(false_branch).map_or(Vec::new(), |s| { vec! [s] });
-                        true_br.append(&mut false_br);
-                        true_br
-                    }
-                }
-            }
-            None => Vec::new()
-        }
+    pub fn predecessors(&self) -> &[BlockId] {
+        &self.predecessors
     }
+}
+
+/// The blocks of a function stored in layout order. Edges are kept both as
+/// the `Link` out of each node and as the list of predecessors going in; the
+/// methods here that change edges keep the two, and the jmp/br instructions
+/// ending each block, consistent with one another.
+#[derive(Debug, Default)]
+pub struct Cfg {
+    nodes: Vec<Node>,
+}
+
+impl Index<BlockId> for Cfg {
+    type Output = Node;
 
-    pub fn prune_missing_predecessors(&self) {
-        let preds: &mut Vec<Weak<Node>> = &mut self.predecessors.borrow_mut();
-        preds.retain(|x| {
-            x.upgrade().is_some()
-        });
+    fn index(&self, id: BlockId) -> &Node {
+        &self.nodes[id.0]
     }
+}
 
-    pub fn insert_id(&self, var: Var, dest: Var, r_type: Type) {
-        let instr = Instr::Value {
-            op: Op::Id,
-            dest: dest,
-            r_type: r_type,
-            args: vec![var],
-            funcs: Vec::new(),
-            labels: Vec::new(),
-        };
-        let block = &mut *self.contents.borrow_mut();
-        block.0.push(instr);
+impl IndexMut<BlockId> for Cfg {
+    fn index_mut(&mut self, id: BlockId) -> &mut Node {
+        &mut self.nodes[id.0]
     }
+}
 
-    pub fn replace_link(&self, old: Label, new_ref: Weak<Node>, new_label: Label) {
-        let out: &mut Option<Link> = &mut self.out.borrow_mut();
-        if out.is_none() {
-            panic!("replace on unlinked block");
-        }
-        let block = &mut *self.contents.borrow_mut();
-
-        if let Some(Link::Fallthrough(_)) = out {
-            out.replace(Link::Jump(new_ref));
-            block.0.push(Instr::Effect{
-                op: Op::Jmp,
-                labels: vec! [new_label],
-                args: Vec::new(),
-                funcs: Vec::new(),
+impl Cfg {
+    pub fn new(instrs: Vec<Instr>, names: &mut Interner) -> Cfg {
+        let mut cfg = Cfg {
+            nodes: construct_basic_blocks(instrs).into_iter().map(|x| Node::from_block(x, names)).collect()
+        };
+        cfg.connect_blocks(names);
+        cfg.rebuild_predecessors();
+        cfg
+    }
+
+    fn connect_blocks(&mut self, names: &Interner) {
+        let label_map: HashMap<Label, BlockId> = self.ids()
+            .filter_map(|id| self[id].block_label().map(|label| (label, id)))
+            .collect();
+        let lookup = |label: &Label| -> BlockId {
+            *label_map.get(label).unwrap_or_else(|| {
+                panic!("Unable to locate label {}", names.resolve(*label))
             })
-        } else if let Instr::Effect { op, labels, .. } = block.0.last_mut().unwrap() {
-            match op {
-                Op::Jmp => {
-                    if labels[0] == old {
-                        labels[0] = new_label;
-                        out.replace(Link::Jump(new_ref));
-                    } else {
-                        // eprintln!("{} - {}", labels[0], old);
-                        panic!("Jump missing")
-                    }
-                },
-                Op::Br => {
-                    if labels[0] == old {
-                        labels[0] = new_label;
-                        if let Some(Link::Branch { true_branch, ..} ) = out {
-                            *true_branch = new_ref;
-                        } else {
-                            panic!("Branch Link missing")
-                        }
-                    } else if labels[1] == old {
-                        labels[1] = new_label;
-                        if let Some(Link::Branch { false_branch, ..} ) = out {
-                            *false_branch = new_ref;
-                        } else {
-                            panic!("Branch Link missing")
-                        }
-                    } else {
-                        panic!("Malformed {} {:?}",old, labels)
-                    }
+        };
+
+        let len = self.nodes.len();
+        for (idx, node) in self.nodes.iter_mut().enumerate() {
+            node.out = match node.contents.last() {
+                Some(Instr::Effect { op: Op::Jmp, labels, .. }) => Link::Jump(lookup(&labels[0])),
+                Some(Instr::Effect { op: Op::Br, labels, .. }) => Link::Branch {
+                    true_branch: lookup(&labels[0]),
+                    false_branch: lookup(&labels[1]),
                 },
-                _ => {}
-            }
-        } else {
-            panic!("??????")
+                Some(Instr::Effect { op: Op::Ret, .. }) => Link::Ret,
+                _ if idx + 1 < len => Link::Fallthrough(BlockId(idx + 1)),
+                _ => Link::Exit,
+            };
         }
     }
 
-
-
-    pub fn add_jump(&self, target: Weak<Node>, label: Label) {
-        let out: &mut Option<Link> = &mut self.out.borrow_mut();
-        if out.is_some() {
-            panic!("Tried to add jump on linked node")
+    /// Flattens the graph back into a list of instructions. Blocks whose
+    /// fallthrough successor no longer directly follows them get an explicit
+    /// jump and blocks that exit the function from the middle get a `ret`.
+    pub fn into_instrs(mut self) -> Vec<Instr> {
+        let len = self.nodes.len();
+        let mut jump_targets = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Link::Fallthrough(target) = node.out {
+                if target.0 != idx + 1 {
+                    jump_targets.push(target);
+                }
+            }
         }
-        let block = &mut *self.contents.borrow_mut();
+        for target in jump_targets {
+            self[target].normalize()
+        }
+
+        let labels: Vec<Label> = self.nodes.iter().map(|x| x.label).collect();
+        let mut instrs = Vec::new();
 
-        if let Some(Instr::Effect {op, .. }) = block.0.last() {
-            if *op == Op::Jmp || *op == Op::Br || *op == Op::Ret {
-                panic!("Adding jump to block that already contains a terminal instr");
+        for (idx, node) in self.nodes.into_iter().enumerate() {
+            let out = node.out;
+            instrs.extend(node.contents);
+            match out {
+                Link::Fallthrough(target) if target.0 != idx + 1 => {
+                    instrs.push(jump_instr(labels[target.0]))
+                }
+                Link::Exit if idx + 1 != len => {
+                    instrs.push(Instr::Effect {
+                        op: Op::Ret,
+                        args: Vec::new(),
+                        funcs: Vec::new(),
+                        labels: Vec::new(),
+                    })
+                }
+                _ => {}
             }
         }
-
-        block.0.push(Instr::Effect {
-            op: Op::Jmp,
-            args: Vec::new(),
-            funcs: Vec::new(),
-            labels: vec! [label],
-        });
-        out.replace(Link::Jump(target));
+        instrs
     }
 
-}
-
-impl PartialEq for Node {
-    fn eq(&self, other: &Self) -> bool {
-        self.label() == other.label()
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
     }
-}
 
+    pub fn entry(&self) -> BlockId {
+        BlockId(0)
+    }
 
-pub fn construct_cfg_nodes(instrs: Vec<Block>) -> Vec<Rc<Node>> {
-    instrs.into_iter().map(|x|{Rc::new(
-        Node::from_block(x)
-    )}).collect()
-}
+    /// Block ids in layout order
+    pub fn ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.nodes.len()).map(BlockId)
+    }
 
-pub fn construct_basic_blocks(instrs: Vec<Instr>) -> Vec<Block> {
-    let mut output = Vec::<Block>::new();
-    let mut cur_block = Vec::<Instr>::new();
-    for instr in instrs.into_iter() {
-        if instr.is_label() {
-            if !cur_block.is_empty() {
-                output.push(Block::new(cur_block));
-            }
-            cur_block = vec![instr];
-        } else if instr.is_terminator() {
-            cur_block.push(instr);
-            output.push(Block::new(cur_block));
-            cur_block = Vec::<Instr>::new();
-        } else {
-            cur_block.push(instr);
-        }
+    pub fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.nodes.iter_mut()
     }
-    if !cur_block.is_empty() {
-        output.push(Block::new(cur_block));
+
+    pub fn successors(&self, id: BlockId) -> Vec<BlockId> {
+        self[id].successors()
     }
-    output
-}
 
-fn construct_label_lookup(blocks: &[Rc<Node>]) -> LabelMap {
-    let mut map = LabelMap::new();
-    for node in blocks.iter() {
-        if node.is_labeled() {
-            map.insert(node.block_label().unwrap(), Rc::clone(node));
-        }
+    pub fn predecessors(&self, id: BlockId) -> &[BlockId] {
+        self[id].predecessors()
     }
-    map
-}
 
-fn connect_block(current: &Rc<Node>, node: &Rc<Node>, map: &LabelMap) {
-    if current.out.borrow().is_some() {
-        return;
+    pub fn label_map(&self) -> HashMap<Label, BlockId> {
+        self.ids().map(|id| (self[id].label(), id)).collect()
     }
 
-    let instrs = &current.contents.borrow();
-    let last = instrs.last();
-    let (op, labels) = match last {
-        Instr::Value { op, labels, .. } | Instr::Effect { op, labels, .. } => (op, labels),
-        _ => {
-            current
-                .out
-                .replace(Some(Link::Fallthrough(Rc::downgrade(node))));
-            let mut vec_cell = node.predecessors.borrow_mut();
-            vec_cell.push(Rc::downgrade(current));
+    pub fn has_phis(&self) -> bool {
+        self.nodes.iter().any(|x| x.contents.iter().any(|instr| matches!(instr, Instr::Value { op: Op::Phi, .. })))
+    }
 
-            return;
-        }
-    };
-    match op {
-        Op::Jmp => {
-            let target = &labels[0];
-            let target_ref = map.get(&target).unwrap_or_else(|| {
-                panic!(
-                    "Unable to locate label {}",
-                    namer().get_string(&(target.0))
-                )
-            });
-            current.out.replace(Some(Link::Jump(Rc::downgrade(target_ref))));
-            let mut vec_cell = target_ref.predecessors.borrow_mut();
-            vec_cell.push(Rc::downgrade(current));
+    pub fn normalize(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.normalize()
         }
-        Op::Br => {
-            let true_label = &labels[0];
-            let false_label = &labels[1];
-
-            let true_target = map.get(&true_label).unwrap_or_else(|| {
-                panic!(
-                    "Unable to locate label {}",
-                    namer().get_string(&(true_label.0))
-                )
-            });
-
-            let false_target = map.get(&false_label).unwrap_or_else(|| {
-                panic!(
-                    "Unable to locate label {}",
-                    namer().get_string(&(false_label.0))
-                )
-            });
+    }
 
-            current.out.replace(Some(Link::Branch {
-                true_branch: Rc::downgrade(true_target),
-                false_branch: Rc::downgrade(false_target),
-            }));
-            let mut vec_cell = true_target.predecessors.borrow_mut();
-            vec_cell.push(Rc::downgrade(current));
+    /// Appends a new block at the end of the layout. A label instruction is
+    /// added to the front of `instrs` if it doesn't already start with one.
+    pub fn add_block(&mut self, label: Label, instrs: Vec<Instr>, out: Link) -> BlockId {
+        let id = BlockId(self.nodes.len());
+        let mut node = Node {
+            contents: Block::new(instrs),
+            out,
+            predecessors: Vec::new(),
+            label
+        };
+        node.normalize();
+        self.nodes.push(node);
 
-            let mut vec_cell = false_target.predecessors.borrow_mut();
-            vec_cell.push(Rc::downgrade(current));
+        for target in out.targets() {
+            self.add_predecessor(target, id);
         }
-        Op::Ret => {
-            current.out.replace(Some(Link::Ret));
+        id
+    }
+
+    fn add_predecessor(&mut self, id: BlockId, pred: BlockId) {
+        let preds = &mut self[id].predecessors;
+        if !preds.contains(&pred) {
+            preds.push(pred)
         }
-        _ => {
-            current
-                .out
-                .replace(Some(Link::Fallthrough(Rc::downgrade(node))));
-            let mut vec_cell = node.predecessors.borrow_mut();
-            vec_cell.push(Rc::downgrade(current));
+    }
+
+    fn remove_predecessor(&mut self, id: BlockId, pred: BlockId) {
+        if !self[pred].successors().contains(&id) {
+            self[id].predecessors.retain(|x| *x != pred)
         }
     }
-}
 
-fn connect_terminal_block( last_block: &Rc<Node>, map: &LabelMap) {
-    let instrs = &last_block.contents.borrow();
-    let last = instrs.last();
-
-    match last {
-        Instr::Value { op, labels, .. } | Instr::Effect { op, labels, .. } => match op {
-            Op::Jmp => {
-                let target = &labels[0];
-                let target_ref = map.get(&target).unwrap_or_else(|| {
-                    panic!(
-                        "Unable to locate label {}",
-                        namer().get_string(&(target.0))
-                    )
-                });
-                last_block
-                    .out
-                    .replace(Some(Link::Jump(Rc::downgrade(target_ref))));
-                    let mut vec_cell = target_ref.predecessors.borrow_mut();
-                    vec_cell.push(Rc::downgrade(last_block));
-                return;
+    /// Retargets every edge from `from` to `old` so it goes to `new` instead,
+    /// rewriting the jump or branch at the end of `from` to match. A
+    /// fallthrough becomes an explicit jump.
+    pub fn redirect(&mut self, from: BlockId, old: BlockId, new: BlockId) {
+        self[new].normalize();
+        let new_label = self[new].label();
+        let node = &mut self[from];
+
+        match &mut node.out {
+            Link::Fallthrough(target) if *target == old => {
+                node.out = Link::Jump(new);
+                node.contents.0.push(jump_instr(new_label));
             }
-            Op::Br => {
-                let true_label = &labels[0];
-                let false_label = &labels[1];
-
-                let true_target = map.get(&true_label).unwrap_or_else(|| {
-                    panic!(
-                        "Unable to locate label {}",
-                        namer().get_string(&(true_label.0))
-                    )
-                });
-
-                let false_target = map.get(&false_label).unwrap_or_else(|| {
-                    panic!(
-                        "Unable to locate label {}",
-                        namer().get_string(&(false_label.0))
-                    )
-                });
-
-                last_block.out.replace(Some(Link::Branch {
-                    true_branch: Rc::downgrade(true_target),
-                    false_branch: Rc::downgrade(false_target),
-                }));
-                let mut vec_cell = true_target.predecessors.borrow_mut();
-                vec_cell.push(Rc::downgrade(last_block));
-
-                let mut vec_cell = false_target.predecessors.borrow_mut();
-                vec_cell.push(Rc::downgrade(last_block));
-                return;
+            Link::Jump(target) if *target == old => {
+                *target = new;
+                node.contents.terminator_labels_mut().expect("Jump missing")[0] = new_label;
             }
-            Op::Ret => {
-                last_block.out.replace(Some(Link::Ret));
-                return;
+            Link::Branch { true_branch, false_branch } if *true_branch == old || *false_branch == old => {
+                let labels = node.contents.terminator_labels_mut().expect("Branch missing");
+                if *true_branch == old {
+                    *true_branch = new;
+                    labels[0] = new_label;
+                }
+                if *false_branch == old {
+                    *false_branch = new;
+                    labels[1] = new_label;
+                }
             }
-            _ => {}
-        },
-        _ => {}
-    };
+            _ => panic!("No edge from {:?} to {:?}", from, old),
+        }
 
-    last_block.out.replace(Some(Link::Exit));
-}
+        self.remove_predecessor(old, from);
+        self.add_predecessor(new, from);
+    }
 
-pub fn connect_basic_blocks(blocks: &mut Vec<Rc<Node>>) {
-    let map = construct_label_lookup(blocks);
-    let mut second_iter = blocks.iter();
-    second_iter.next();
+    /// Replaces the jump, branch or return ending `from` with a jump to
+    /// `target`, or adds one if the block had none.
+    pub fn set_jump(&mut self, from: BlockId, target: BlockId) {
+        self[target].normalize();
+        let label = self[target].label();
+        self.set_terminator(from, jump_instr(label), Link::Jump(target));
+    }
 
-    for (current, node) in blocks.iter().zip(second_iter) {
-        connect_block(current, node, &map)
+    /// Replaces the jump, branch or return ending `from` with a branch on
+    /// `cond`, or adds one if the block had none.
+    pub fn set_branch(&mut self, from: BlockId, cond: Var, true_branch: BlockId, false_branch: BlockId) {
+        self[true_branch].normalize();
+        self[false_branch].normalize();
+        let instr = Instr::Effect {
+            op: Op::Br,
+            args: vec![cond],
+            funcs: Vec::new(),
+            labels: vec![self[true_branch].label(), self[false_branch].label()],
+        };
+        self.set_terminator(from, instr, Link::Branch { true_branch, false_branch });
     }
 
-    connect_terminal_block(blocks.last().unwrap(), &map);
-    let mut replacement_map: HashMap<Label, Weak<Node>> = HashMap::new();
+    fn set_terminator(&mut self, from: BlockId, instr: Instr, out: Link) {
+        let old_targets = self.successors(from);
 
-    for node in blocks.iter() {
-        if let Some(Link::Fallthrough(x)) = &*node.out.borrow() {
-            if node.block_label().is_some() && node.contents.borrow().0.len() == 1 {
-                replacement_map.insert(node.label(), x.clone());
-            }
+        let node = &mut self[from];
+        if matches!(node.contents.last(), Some(Instr::Effect { op, .. }) if op.is_terminator()) {
+            node.contents.0.pop();
+        }
+        node.contents.0.push(instr);
+        node.out = out;
+
+        for old in old_targets {
+            self.remove_predecessor(old, from);
+        }
+        for target in out.targets() {
+            self.add_predecessor(target, from);
         }
     }
-    let mut update: Vec<(Label,Label)> = Vec::new();
-    for node in blocks.iter() {
-        node.clear_predecessors();
-        let contents = &*node.contents.borrow();
-        if let Some(x) = contents.0.last() {
-            if let Instr::Effect { op, labels, .. } = x {
-
-                if *op == Op::Jmp || *op == Op::Br {
-                    for label in labels.iter() {
-                        if replacement_map.contains_key(label) {
-                            update.push((node.label(), *label));
-                        }
-                    }
 
+    /// Appends a copy of each of the blocks in `ids` under a fresh label and
+    /// returns where each copy went. Edges between the given blocks become
+    /// edges between their copies, while edges leaving the group still go to
+    /// the original targets.
+    pub fn duplicate(&mut self, ids: &[BlockId], names: &mut Interner) -> HashMap<BlockId, BlockId> {
+        let start = self.nodes.len();
+        let copies: HashMap<BlockId, BlockId> = ids.iter()
+            .enumerate()
+            .map(|(idx, id)| (*id, BlockId(start + idx)))
+            .collect();
+        let labels: HashMap<Label, Label> = ids.iter()
+            .map(|id| (self[*id].label(), names.fresh_label()))
+            .collect();
+
+        for id in ids {
+            let node = &self[*id];
+            let mut contents = Block::new(node.contents.iter().filter(|x| !x.is_label()).cloned().collect());
+            if let Some(targets) = contents.terminator_labels_mut() {
+                for target in targets.iter_mut() {
+                    if let Some(label) = labels.get(target) {
+                        *target = *label;
+                    }
                 }
             }
+            let mut out = node.out;
+            out.remap(|x| copies.get(&x).copied().unwrap_or(x));
+
+            let mut copy = Node {
+                contents,
+                out,
+                predecessors: Vec::new(),
+                label: labels[&node.label()],
+            };
+            copy.normalize();
+            self.nodes.push(copy);
         }
-        if let Some(Link::Fallthrough(x)) = &*node.out.borrow() {
-            let x_lab = x.upgrade().unwrap().label();
-            for (label, reference) in replacement_map.iter(){
-                if x_lab == *label {
-                    update.push((node.label(), *label));
-                    break
-                }
+
+        for idx in start..self.nodes.len() {
+            for target in self.nodes[idx].successors() {
+                self.add_predecessor(target, BlockId(idx));
             }
         }
+        copies
     }
 
-    for (node, target) in update {
-        let new_ref = &replacement_map[&target];
-        let new_label= new_ref.upgrade().unwrap().label();
-        map[&node].replace_link(target, new_ref.clone(), new_label)
+    pub fn rebuild_predecessors(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.predecessors.clear();
+        }
+        for id in self.ids() {
+            for successor in self.successors(id) {
+                self.add_predecessor(successor, id);
+            }
+        }
     }
 
-    repair_predecessor_links(blocks);
-
- }
+    /// Blocks reachable from the entry, in reverse post-order
+    pub fn reverse_post_order(&self) -> Vec<BlockId> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        if self.nodes.is_empty() {
+            return order;
+        }
 
-pub fn repair_predecessor_links(nodes: &mut Vec<Rc<Node>>) {
-    let mut label_map: HashMap<Label, Rc<Node>> = HashMap::new();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack: Vec<(BlockId, Vec<BlockId>)> = vec![(self.entry(), self.successors(self.entry()))];
+        visited[self.entry().0] = true;
 
-    for node in nodes.iter() {
-        match &*node.out.borrow() {
-            Some(x) => {
-                match x {
-                    Link::Fallthrough(successor)
-                    | Link::Jump(successor) => {
-                        successor.upgrade().unwrap().predecessors.borrow_mut().push(Rc::downgrade(node));
-                    }
-                    Link::Branch { true_branch, false_branch } => {
-                        true_branch.upgrade().unwrap().predecessors.borrow_mut().push(Rc::downgrade(node));
-                        false_branch.upgrade().unwrap().predecessors.borrow_mut().push(Rc::downgrade(node));
-                    }
-                    _ => {}
+        while let Some((id, successors)) = stack.last_mut() {
+            if let Some(next) = successors.pop() {
+                if !visited[next.0] {
+                    visited[next.0] = true;
+                    let next_successors = self.successors(next);
+                    stack.push((next, next_successors));
                 }
+            } else {
+                order.push(*id);
+                stack.pop();
             }
-            None => {}
         }
+
+        order.reverse();
+        order
     }
-}
 
-impl Display for Node {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(label) = &self.block_label() {
-            writeln!(f, "Block {}:", namer().get_string(&label.0))?;
-        } else {
-            writeln!(f, "Block (unlabeled):")?;
+    /// Drops the given blocks, none of which may be the entry or the target of
+    /// an edge from a block that is kept. All existing ids are invalidated.
+    pub fn remove_blocks(&mut self, dead: &HashSet<BlockId>) {
+        if dead.is_empty() {
+            return;
         }
-        for line in self.contents.borrow_mut().iter() {
-            writeln!(f,"     {}", line)?;
+        assert!(!dead.contains(&self.entry()), "Tried to remove the entry block");
+
+        let mut remapping = Vec::with_capacity(self.nodes.len());
+        let mut next = 0;
+        for idx in 0..self.nodes.len() {
+            remapping.push(BlockId(next));
+            if !dead.contains(&BlockId(idx)) {
+                next += 1;
+            }
         }
-        if let Some(x) = self.out.borrow().as_ref() {
-            write!(f, " Connected to: {}", x)
-        } else {
-            write!(f, "  not connected?")
+
+        let nodes = std::mem::take(&mut self.nodes);
+        for (idx, mut node) in nodes.into_iter().enumerate() {
+            if dead.contains(&BlockId(idx)) {
+                continue;
+            }
+            node.out.remap(|x| {
+                assert!(!dead.contains(&x), "Removed block {} is still a successor", x.0);
+                remapping[x.0]
+            });
+            node.predecessors = node.predecessors.iter()
+                .filter(|x| !dead.contains(x))
+                .map(|x| remapping[x.0])
+                .collect();
+            self.nodes.push(node);
         }
     }
-}
-
-
 
-impl Display for Link {
-    fn fmt(&self, f: & mut fmt::Formatter<'_>) -> fmt::Result {
-        let namer = namer();
-        match &self {
-            Link::Ret => {
-                write!(f, "<RETURN>")
-            }
-            Link::Exit => {
-                write!(f, "<EXIT>")
-            }
-            Link::Fallthrough(val) => {
-                let val = val.upgrade();
-                if let Some(val) = val {
-                    match val.block_label() {
-                        Some(label) => {
-                            write!(f, "<FALLTHROUGH: .{}>", namer.get_string(&label.0))
-                        }
-                        None => {
-                            // Is this possible???
-                            write!(f, "<FALLTHROUGH: Unlabeled Block>")
-                        }
-                    }
-                } else {
-                    write!(f, "?? LOST CONNECTION ??")
-                }
-            }
-            Link::Jump(val) => {
-                let val = val.upgrade();
-                if let Some(val) = val {
-                    if let Some(label) = val.block_label() {
-                        write!(f, "<JUMP: .{}>", namer.get_string(&label.0))?
-                    }
-                    Ok(())
-                } else {
-                    write!(f, "?? LOST CONNECTION ??")
-                }
-            }
-            Link::Branch { true_branch, false_branch } => {
-                let val = true_branch.upgrade();
-                if let Some(val) = val {
-                    if let Some(label) = val.block_label() {
-                        write!(f, "<BR TRUE: .{}>", namer.get_string(&label.0))?;
-                    }
-                } else {
-                    write!(f, "<BR TRUE:?? LOST CONNECTION ??>")?;
-                }
+    pub fn remove_unreachable(&mut self) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let reachable: HashSet<BlockId> = self.reverse_post_order().into_iter().collect();
+        let dead: HashSet<BlockId> = self.ids().filter(|x| !reachable.contains(x)).collect();
+        self.remove_blocks(&dead);
+    }
+}
 
-                write!(f, " ")?;
-                let val = false_branch.upgrade();
-                if let Some(val) = val {
-                    if let Some(label) = val.block_label(){
-                        write!(f, "<BR FALSE: .{}>", namer.get_string(&label.0))
-                    } else {
-                        Ok(())
-                    }
-                } else {
-                    write!(f, "<BR FALSE:?? LOST CONNECTION ??>")
-                }
+fn jump_instr(label: Label) -> Instr {
+    Instr::Effect {
+        op: Op::Jmp,
+        args: Vec::new(),
+        funcs: Vec::new(),
+        labels: vec![label],
+    }
+}
 
+pub fn construct_basic_blocks(instrs: Vec<Instr>) -> Vec<Block> {
+    let mut output = Vec::<Block>::new();
+    let mut cur_block = Vec::<Instr>::new();
+    for instr in instrs.into_iter() {
+        if instr.is_label() {
+            if !cur_block.is_empty() {
+                output.push(Block::new(cur_block));
             }
+            cur_block = vec![instr];
+        } else if instr.is_terminator() {
+            cur_block.push(instr);
+            output.push(Block::new(cur_block));
+            cur_block = Vec::<Instr>::new();
+        } else {
+            cur_block.push(instr);
         }
     }
+    if !cur_block.is_empty() {
+        output.push(Block::new(cur_block));
+    }
+    output
 }
@@ -1,80 +1,119 @@
-use std::collections::HashMap;
-use clap::Values;
-pub const ALLOWED_VALUES: &[&str] = &["all", "g_tdce", "l_tdce", "lvn", "orph", "solo_lvn", "to_ssa", "from_ssa"];
+use super::passes::{PassManager, Stage, GlobalTdce, LocalTdce, GlobalDce, OrphanBlocks, Lvn, SuperlocalLvn, ToSsa, FromSsa, Licm, StrengthReduce, Unroll, Peel, LoopSimplify, Rotate, Unswitch, DeleteLoops, Cprop, Sccp, Gvn, CopyProp};
 
-pub enum LVNChoice {
-    Solo,
-    Bool(bool)
-}
+pub const ALLOWED_VALUES: &[&str] = &["all", "g_tdce", "l_tdce", "gdce", "lvn", "orph", "solo_lvn", "superlocal_lvn", "to_ssa", "from_ssa", "licm", "strength_reduce", "unroll", "peel", "loop_simplify", "rotate", "unswitch", "delete_loops", "cprop", "sccp", "gvn", "copyprop"];
 
-impl LVNChoice {
-    pub fn run_lvn(&self) -> bool {
-        match self {
-            LVNChoice::Solo => true,
-            LVNChoice::Bool(b) => *b
-        }
-    }
+pub const OPT_LEVELS: &[&str] = &["0", "1", "2"];
 
-    pub fn run_solo(&self) -> bool {
-        match self {
-            LVNChoice::Solo => true,
-            LVNChoice::Bool(_) => false
-        }
-    }
+/// Settings for the passes that take a parameter, given on the command line
+/// alongside the options themselves
+pub struct PassParams {
+    /// How many copies of the body `unroll` makes when it can't unroll a
+    /// loop completely
+    pub unroll_factor: usize,
+    /// How many iterations `peel` takes off the front of each loop
+    pub peel_count: usize,
+    /// How many instructions `unswitch` may add to each function
+    pub unswitch_budget: usize,
+}
 
-    pub fn run_normal(&self) -> bool {
-        match self {
-            LVNChoice::Solo => false,
-            LVNChoice::Bool(b) => *b
-        }
+impl Default for PassParams {
+    fn default() -> Self {
+        PassParams { unroll_factor: 4, peel_count: 1, unswitch_budget: 100 }
     }
 }
 
-pub struct ConfigOptions {
-    pub orphan_block: bool,
-    pub l_tdce: bool,
-    pub g_tdce: bool,
-    pub to_ssa: bool,
-    pub from_ssa: bool,
-    pub lvn: LVNChoice
+fn preset(level: &str) -> &'static [&'static str] {
+    match level {
+        "1" => &["orph", "l_tdce", "g_tdce"],
+        "2" => &["orph", "lvn"],
+        _ => &[],
+    }
 }
 
-impl ConfigOptions {
-    fn config_map(options: Values) -> HashMap<&str, bool> {
-        let mut hash = HashMap::<&str, bool>::new();
-        for opt in options {
-            hash.insert(opt, true);
-        }
-
-        if hash.contains_key("all") {
-            for key in ALLOWED_VALUES {
-                hash.insert(&key, true);
-            }
-        } else {
-            for key in ALLOWED_VALUES {
-                if !hash.contains_key(key) {
-                    hash.insert(&key, false);
-                }
+/// Appends the passes an `-o` option stands for. Most options are a single
+/// pass, but `lvn`, `superlocal_lvn`, `cprop`, `sccp`, `gvn`, `copyprop`,
+/// `strength_reduce`, `unroll`, `peel`, `rotate` and `delete_loops` bring
+/// along the passes that prepare for or clean up after them, and `all` runs
+/// everything.
+pub fn add_option(manager: &mut PassManager, option: &str, params: &PassParams) {
+    match option {
+        "all" => {
+            for opt in ["g_tdce", "orph", "l_tdce", "lvn", "to_ssa", "from_ssa"].iter() {
+                add_option(manager, opt, params)
             }
         }
-
-        hash
+        "g_tdce" => manager.add(Stage::Linear(Box::new(GlobalTdce))),
+        "l_tdce" => manager.add(Stage::Cfg(Box::new(LocalTdce))),
+        "gdce" => manager.add(Stage::Cfg(Box::new(GlobalDce))),
+        "orph" => manager.add(Stage::Cfg(Box::new(OrphanBlocks))),
+        "solo_lvn" => manager.add(Stage::Cfg(Box::new(Lvn))),
+        "lvn" => {
+            manager.add(Stage::Cfg(Box::new(Lvn)));
+            manager.add(Stage::Cfg(Box::new(LocalTdce)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "superlocal_lvn" => {
+            manager.add(Stage::Cfg(Box::new(SuperlocalLvn)));
+            manager.add(Stage::Cfg(Box::new(LocalTdce)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "cprop" => {
+            manager.add(Stage::Cfg(Box::new(Cprop)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "sccp" => {
+            manager.add(Stage::Cfg(Box::new(Sccp)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "gvn" => {
+            manager.add(Stage::Cfg(Box::new(Gvn)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "copyprop" => {
+            manager.add(Stage::Cfg(Box::new(CopyProp)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "to_ssa" => manager.add(Stage::Cfg(Box::new(ToSsa))),
+        "from_ssa" => manager.add(Stage::Cfg(Box::new(FromSsa))),
+        "licm" => manager.add(Stage::Cfg(Box::new(Licm))),
+        "strength_reduce" => {
+            manager.add(Stage::Cfg(Box::new(StrengthReduce)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "unroll" => {
+            manager.add(Stage::Cfg(Box::new(Unroll { factor: params.unroll_factor })));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "loop_simplify" => manager.add(Stage::Cfg(Box::new(LoopSimplify))),
+        "rotate" => {
+            manager.add(Stage::Cfg(Box::new(Rotate)));
+            manager.add(Stage::Cfg(Box::new(LoopSimplify)));
+        }
+        "unswitch" => manager.add(Stage::Cfg(Box::new(Unswitch { budget: params.unswitch_budget }))),
+        "delete_loops" => {
+            manager.add(Stage::Cfg(Box::new(DeleteLoops)));
+            manager.add(Stage::Linear(Box::new(GlobalTdce)));
+        }
+        "peel" => manager.add(Stage::Cfg(Box::new(Peel { count: params.peel_count }))),
+        _ => panic!("Unknown optimization {}", option),
     }
+}
 
-    pub fn new(options: Values) -> ConfigOptions {
-        let map = ConfigOptions::config_map(options);
+/// Builds the pipeline for an optional `-O` preset followed by the explicit
+/// `-o` options, in the order they were given.
+pub fn build_pipeline<'a, I>(level: Option<&str>, options: I, params: &PassParams) -> PassManager
+    where I: IntoIterator<Item = &'a str> {
+    let mut manager = PassManager::new();
 
-        let mut lvn = LVNChoice::Bool(map["lvn"]);
-        if map["solo_lvn"] && !map["lvn"] {
-            lvn = LVNChoice::Solo
-        }
-        ConfigOptions {
-            orphan_block: map["orph"],
-            l_tdce: map["l_tdce"],
-            g_tdce: map["g_tdce"],
-            lvn,
-            to_ssa: map["to_ssa"],
-            from_ssa: map["from_ssa"]
+    if let Some(level) = level {
+        for opt in preset(level) {
+            add_option(&mut manager, opt, params)
         }
     }
+
+    for opt in options {
+        add_option(&mut manager, opt, params)
+    }
+
+    manager
 }
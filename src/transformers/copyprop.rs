@@ -0,0 +1,52 @@
+use super::cfg::Cfg;
+use crate::analysis::copies::{self, available_copies};
+use crate::serde_structs::structs::{Instr, Op};
+use std::collections::HashSet;
+
+/// Replaces each use of a variable that holds a copy of another with the
+/// variable it was copied from, as long as the copy is made on every path to
+/// the use and neither has been assigned since. Copies of a variable into
+/// itself, which this can leave behind, are removed. The copies are otherwise
+/// left for dead code elimination once nothing uses them.
+pub fn propagate_copies(cfg: &mut Cfg) {
+    if cfg.is_empty() {
+        return;
+    }
+
+    let facts = available_copies(cfg);
+    // Blocks that can't be reached may never have had their facts worked out
+    let reachable: HashSet<_> = cfg.reverse_post_order().into_iter().collect();
+    let ids: Vec<_> = cfg.ids().filter(|x| reachable.contains(x)).collect();
+
+    for id in ids {
+        let entry = &facts[id.index()].in_data;
+        let mut data = entry.clone();
+        let instrs = std::mem::take(&mut cfg[id].contents.0);
+
+        for mut instr in instrs {
+            match &mut instr {
+                // Phis read their arguments at the end of each predecessor,
+                // where everything available on entry to the block was too
+                Instr::Value { op: Op::Phi, args, .. } => {
+                    for arg in args.iter_mut() {
+                        *arg = copies::source(entry, *arg);
+                    }
+                }
+                Instr::Value { args, .. } | Instr::Effect { args, .. } => {
+                    for arg in args.iter_mut() {
+                        *arg = copies::source(&data, *arg);
+                    }
+                }
+                _ => {}
+            }
+
+            if let Instr::Value { op: Op::Id, dest, args, .. } = &instr {
+                if args[0] == *dest {
+                    continue;
+                }
+            }
+            copies::step(&mut data, &instr);
+            cfg[id].contents.0.push(instr);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use super::cfg::{BlockId, Cfg, Link};
+use crate::analysis::cprop;
+use crate::serde_structs::structs::{FnHeaders, Instr, Label, Literal, Op};
+use std::collections::HashSet;
+
+// Folds what it can in a single pass over the function, returning the
+// branches whose direction is known
+fn fold(cfg: &mut Cfg, args: &[FnHeaders]) -> Vec<(BlockId, BlockId)> {
+    let facts = cprop::constant_propagation(cfg, args);
+    let mut jumps = Vec::new();
+
+    for (id, node) in cfg.ids().zip(facts) {
+        let mut data = node.in_data;
+        let link = cfg[id].link();
+        for instr in cfg[id].contents.iter_mut() {
+            cprop::step(&mut data, instr);
+            match instr {
+                Instr::Value { dest, r_type, .. } => {
+                    // Infinities and NaN have no way of being written as a literal
+                    let value = data.get(dest)
+                        .and_then(|x| x.to_literal())
+                        .filter(|x| !matches!(x, Literal::Float(f) if !f.is_finite()));
+                    if let Some(value) = value {
+                        *instr = Instr::Const { op: Op::Const, dest: *dest, r_type: r_type.clone(), value };
+                    }
+                }
+                Instr::Effect { op: Op::Br, args, .. } => {
+                    if let (Some(cprop::Value::Bool(cond)), Link::Branch { true_branch, false_branch }) =
+                        (data.get(&args[0]), link) {
+                        jumps.push((id, if *cond { true_branch } else { false_branch }));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    jumps
+}
+
+// Drops the phi arguments coming from blocks that are no longer predecessors
+// once a branch has become a jump or its block has been removed
+fn prune_phis(cfg: &mut Cfg) {
+    let ids: Vec<BlockId> = cfg.ids().collect();
+    for id in ids {
+        let preds: HashSet<Label> = cfg.predecessors(id).iter().map(|x| cfg[*x].label()).collect();
+        for instr in cfg[id].contents.iter_mut() {
+            if let Instr::Value { op: Op::Phi, args, labels, .. } = instr {
+                let mut idx = 0;
+                while idx < args.len() {
+                    if preds.contains(&labels[idx]) {
+                        idx += 1;
+                    } else {
+                        args.remove(idx);
+                        labels.remove(idx);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replaces every value operation whose result constant propagation can work
+/// out with a `const`, and turns branches on a known condition into jumps.
+/// Removing the blocks those branches no longer reach can make more values
+/// known, so this repeats until no more branches are decided. Phis lose the
+/// arguments from edges that were taken away.
+pub fn propagate_constants(cfg: &mut Cfg, args: &[FnHeaders]) {
+    if cfg.is_empty() {
+        return;
+    }
+
+    loop {
+        let jumps = fold(cfg, args);
+        if jumps.is_empty() {
+            break;
+        }
+        for (id, target) in jumps {
+            cfg.set_jump(id, target);
+        }
+        cfg.remove_unreachable();
+        prune_phis(cfg);
+    }
+}
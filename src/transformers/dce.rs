@@ -1,14 +1,20 @@
 use std::collections::{HashSet};
-use super::cfg::{Node};
-use crate::serde_structs::structs::{Var, Instr, };
+use super::cfg::{Block, Cfg};
+use crate::analysis::live_variables;
+use crate::serde_structs::structs::{Var, Instr, Op};
 
-pub fn local_dce(node: &Node) {
-    let mut block = node.contents.borrow_mut();
-    let tmp = std::mem::replace(&mut block.0, Vec::new());
+pub fn local_dce(block: &mut Block) {
+    let tmp = std::mem::take(&mut block.0);
     block.0 = trivial_local_dce(tmp);
 }
 
 
+// Value operations that do something besides produce a result. `div` can
+// trap on a zero divisor, so it counts too.
+fn has_effects(op: &Op) -> bool {
+    matches!(op, Op::Call | Op::Alloc | Op::Load | Op::Div)
+}
+
 pub fn trivial_local_dce(instrs: Vec<Instr>) -> Vec<Instr> {
 
     let mut used = HashSet::<Var>::new();
@@ -28,8 +34,8 @@ pub fn trivial_local_dce(instrs: Vec<Instr>) -> Vec<Instr> {
                     (true, x)
                 }
             },
-            Instr::Value {ref dest, ref args, ..} => {
-                if used.contains(dest) {
+            Instr::Value {ref dest, ref args, ref op, ..} => {
+                if used.contains(dest) || has_effects(op) {
                     used.remove(dest);
                     for v in args.iter() {
                         used.insert(*v);
@@ -73,8 +79,10 @@ pub fn trivial_global_dce(nodes: &mut Vec<Instr>) {
     for instr in nodes.iter() {
         match instr {
             Instr::Const { dest, .. } => { defined.insert(*dest); }
-            Instr::Value { dest, args,.. } => {
-                defined.insert(*dest);
+            Instr::Value { dest, args, op, .. } => {
+                if !has_effects(op) {
+                    defined.insert(*dest);
+                }
                 for arg in args.iter() {
                     used.insert(*arg);
                 }}
@@ -97,6 +105,7 @@ pub fn trivial_global_dce(nodes: &mut Vec<Instr>) {
 
     nodes.retain(|x| -> bool {
         match x {
+            Instr::Value { op, .. } if has_effects(op) => true,
             Instr::Const { dest, .. } | Instr::Value { dest, .. } => {!delete_set.contains(dest)}
             _ => true
         }
@@ -105,3 +114,54 @@ pub fn trivial_global_dce(nodes: &mut Vec<Instr>) {
     repeat = !delete_set.is_empty();
 }
 }
+
+// Walks a block backwards from the variables live at its end, dropping
+// definitions nothing later reads. Returns whether anything was removed.
+fn remove_dead_defs(block: &mut Block, mut live: HashSet<Var>) -> bool {
+    let before = block.0.len();
+    let mut keep = Vec::with_capacity(before);
+
+    for instr in std::mem::take(&mut block.0).into_iter().rev() {
+        match &instr {
+            Instr::Const { dest, .. } => {
+                if !live.remove(dest) {
+                    continue;
+                }
+            }
+            Instr::Value { op, dest, args, .. } => {
+                // These have effects beyond their result, so they stay
+                if !live.remove(dest) && !has_effects(op) {
+                    continue;
+                }
+                live.extend(args.iter().copied());
+            }
+            Instr::Effect { args, .. } => live.extend(args.iter().copied()),
+            Instr::Label { .. } => {}
+        }
+        keep.push(instr);
+    }
+
+    keep.reverse();
+    block.0 = keep;
+    block.0.len() != before
+}
+
+/// Dead code elimination driven by liveness. Each assignment whose value is
+/// never read before the variable is assigned again, or before the function
+/// returns, is removed, including earlier assignments to variables that are
+/// used later on. Calls, allocations, loads and divisions, which can trap,
+/// are always kept. Removing one assignment can leave those feeding it dead
+/// too, so this repeats until nothing changes.
+pub fn live_dce(cfg: &mut Cfg) {
+    loop {
+        let live = live_variables(cfg);
+        let ids: Vec<_> = cfg.ids().collect();
+        let mut changed = false;
+        for (id, node) in ids.into_iter().zip(live) {
+            changed |= remove_dead_defs(&mut cfg[id].contents, node.out_data);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
@@ -1,183 +1,326 @@
-use super::cfg::{Node, Block};
-use crate::serde_structs::structs::{Instr, Label};
-use std::rc::{Rc, Weak};
-use std::collections::{HashMap, HashSet};
-use crate::analysis::dehydrated::{set_intersection, set_union};
-
-use std::collections::VecDeque;
-
+use super::cfg::{Cfg, BlockId, Link};
+
+// Dominance over a graph of `0..n` given as successor and predecessor lists.
+// This is shared between the dominator tree, which works on the CFG, and the
+// post-dominator tree, which works on the reversed CFG.
+pub(super) struct DomCore {
+    root: usize,
+    idoms: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    // Entry and exit times of a walk over the dominator tree, which let
+    // `dominates` answer without walking the tree
+    pre: Vec<usize>,
+    post: Vec<usize>,
+    frontiers: Vec<Vec<usize>>,
+}
 
+fn reverse_post_order(root: usize, successors: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(successors.len());
+    let mut visited = vec![false; successors.len()];
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+
+    while let Some((node, next)) = stack.last_mut() {
+        if let Some(succ) = successors[*node].get(*next).copied() {
+            *next += 1;
+            if !visited[succ] {
+                visited[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            order.push(*node);
+            stack.pop();
+        }
+    }
 
-fn reverse_post_order(root: &Rc<Node>) -> Vec<Rc<Node>> {
-    let mut process_queue = Vec::<Rc<Node>>::new();
-    let mut exit_queue = Vec::<Rc<Node>>::new();
+    order.reverse();
+    order
+}
 
-    process_queue.push(root.clone());
+impl DomCore {
+    /// Cooper, Harvey and Kennedy's "A Simple, Fast Dominance Algorithm".
+    /// Nodes that can't be reached from `root` get no immediate dominator.
+    pub(super) fn new(root: usize, successors: &[Vec<usize>], predecessors: &[Vec<usize>]) -> Self {
+        let len = successors.len();
+        let order = reverse_post_order(root, successors);
+        let mut rpo_number = vec![usize::MAX; len];
+        for (idx, node) in order.iter().enumerate() {
+            rpo_number[*node] = idx;
+        }
 
-    while let Some(current) = process_queue.last() {
-        // eprintln!("process_queue {:?}", process_queue.len());
-        let successors = current.successor_refs();
+        let mut idoms: Vec<Option<usize>> = vec![None; len];
+        idoms[root] = Some(root);
 
-        if successors.is_empty() {
-            // eprintln!("no successors");
-            exit_queue.push(process_queue.pop().unwrap());
-        } else {
-            // eprintln!("successors!");
-            let not_processed: Vec<&Rc<Node>> = successors.iter().filter(|x| !exit_queue.contains(x) && *x != current).collect();
-            if not_processed.is_empty() {
-                // eprintln!("all successors processed!");
-                exit_queue.push(process_queue.pop().unwrap());
-            } else if not_processed.iter().all(|x| process_queue.contains(x)) {
-                    exit_queue.push(process_queue.pop().unwrap());
-                } else {
-                    for item in not_processed.into_iter() {
-                    if !process_queue.contains(item) {
-                        // eprintln!("pushing {}", item.label());
-                        process_queue.push(item.clone());
+        let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| -> usize {
+            while a != b {
+                while rpo_number[a] > rpo_number[b] {
+                    a = idoms[a].unwrap();
+                }
+                while rpo_number[b] > rpo_number[a] {
+                    b = idoms[b].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for node in order.iter().skip(1).copied() {
+                let mut new_idom = None;
+                for pred in predecessors[node].iter().copied() {
+                    if idoms[pred].is_none() {
+                        continue;
                     }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(current) => intersect(&idoms, pred, current),
+                    });
+                }
+                if new_idom.is_some() && idoms[node] != new_idom {
+                    idoms[node] = new_idom;
+                    changed = true;
                 }
             }
         }
-    }
+        // The root is its own dominator only for the sake of the algorithm
+        idoms[root] = None;
 
-    exit_queue.reverse();
-    exit_queue
-}
+        let mut children = vec![Vec::new(); len];
+        for (node, idom) in idoms.iter().enumerate() {
+            if let Some(idom) = idom {
+                children[*idom].push(node);
+            }
+        }
 
+        let mut pre = vec![usize::MAX; len];
+        let mut post = vec![usize::MAX; len];
+        let mut clock = 0;
+        let mut stack = vec![(root, 0)];
+        pre[root] = clock;
+        while let Some((node, next)) = stack.last_mut() {
+            clock += 1;
+            if let Some(child) = children[*node].get(*next).copied() {
+                *next += 1;
+                pre[child] = clock;
+                stack.push((child, 0));
+            } else {
+                post[*node] = clock;
+                stack.pop();
+            }
+        }
 
-pub fn determine_dominators(nodes: &[Rc<Node>]) -> HashMap<Label, HashSet<Label>> {
-    let mut label_map = HashMap::<Label, HashSet<Label>>::new();
-    let ordering = reverse_post_order(&nodes[0]);
-    // assert!(ordering.len() == nodes.len(), "ordering: {}, nodes: {}", ordering.len(), nodes.len());
-    {
-        let mut dom_set = HashSet::<Label>::new();
-        for node in ordering.iter() {
-            dom_set.insert(node.label());
+        // Each join point is in the frontier of every block on the way up the
+        // tree from its predecessors to its immediate dominator. The root has
+        // no immediate dominator, so walks into it continue through it.
+        let mut frontiers: Vec<Vec<usize>> = vec![Vec::new(); len];
+        for node in order.iter().copied() {
+            for pred in predecessors[node].iter().copied() {
+                if pred != root && idoms[pred].is_none() {
+                    continue;
+                }
+                let mut runner = Some(pred);
+                while runner.is_some() && runner != idoms[node] {
+                    let current = runner.unwrap();
+                    if !frontiers[current].contains(&node) {
+                        frontiers[current].push(node);
+                    }
+                    runner = idoms[current];
+                }
+            }
         }
-        for node in ordering.iter(){
-            // eprintln!("inserted {}", node.label());
-            label_map.insert(node.label(), dom_set.clone());
+        for frontier in frontiers.iter_mut() {
+            frontier.sort();
         }
+
+        DomCore { root, idoms, children, pre, post, frontiers }
     }
 
-    let mut changed = true;
+    pub(super) fn is_reachable(&self, node: usize) -> bool {
+        node == self.root || self.idoms[node].is_some()
+    }
 
-    while changed {
-        changed = false;
-        for node in ordering.iter() {
-            let preds = node.predecessor_labels();
-            let sets: Vec<&HashSet<Label>> = preds.into_iter().map(|x| {&label_map[&x]}).collect();
+    pub(super) fn idom(&self, node: usize) -> Option<usize> {
+        self.idoms[node]
+    }
 
-            let intersect = set_intersection(sets);
-            let mut current = HashSet::<Label>::with_capacity(1);
-            current.insert(node.label());
-            let new_value = set_union(vec! [&intersect, &current ]);
+    pub(super) fn children(&self, node: usize) -> &[usize] {
+        &self.children[node]
+    }
 
-            if new_value != label_map[&node.label()] {
-                changed = true;
-                label_map.insert(node.label(), new_value);
-            }
-        }
+    pub(super) fn frontier(&self, node: usize) -> &[usize] {
+        &self.frontiers[node]
+    }
 
+    pub(super) fn dominates(&self, a: usize, b: usize) -> bool {
+        self.is_reachable(a) && self.is_reachable(b)
+            && self.pre[a] <= self.pre[b] && self.post[b] <= self.post[a]
     }
-    label_map
+}
+
+fn to_ids(nodes: &[usize]) -> impl Iterator<Item = BlockId> + '_ {
+    nodes.iter().map(|x| BlockId::from_index(*x))
 }
 
 pub struct DominanceTree {
-    nodes: HashMap<Label, Rc<Node>>,
-    dominated_map: HashMap<Label, HashSet<Label>>,
-    dom_tree: HashMap<Label, Vec<Label>>,
-    root_label: Label
+    core: DomCore,
 }
 
 impl DominanceTree {
-    pub fn new(nodes: &[Rc<Node>]) -> Self {
-        let mut node_map = HashMap::<Label, Rc<Node>>::new();
-        for node in nodes {
-            node_map.insert(node.label(), node.clone());
-        }
-
-        let (dom_tree, dominated_map) = construct_dominance_tree(nodes);
+    pub fn new(cfg: &Cfg) -> Self {
+        let successors: Vec<Vec<usize>> = cfg.ids()
+            .map(|id| cfg.successors(id).iter().map(|x| x.index()).collect())
+            .collect();
+        let predecessors: Vec<Vec<usize>> = cfg.ids()
+            .map(|id| cfg.predecessors(id).iter().map(|x| x.index()).collect())
+            .collect();
 
         DominanceTree {
-            nodes: node_map,
-            dom_tree,
-            dominated_map,
-            root_label: nodes[0].label()
+            core: DomCore::new(cfg.entry().index(), &successors, &predecessors)
         }
     }
 
-    pub fn lookup_node(&self, label: &Label) -> &Rc<Node> {
-        &self.nodes[label]
+    pub fn root(&self) -> BlockId {
+        BlockId::from_index(self.core.root)
     }
 
-    pub fn root_node(&self) -> &Rc<Node> {
-        self.lookup_node(&self.root_label)
+    pub fn idom(&self, id: BlockId) -> Option<BlockId> {
+        self.core.idom(id.index()).map(BlockId::from_index)
     }
 
-    pub fn get_children(&self, label: &Label) -> Vec<Rc<Node>> {
-        self.dom_tree[label].iter()
-            .map(|x| {self.lookup_node(x)})
-            .cloned()
+    /// Whether every path from the entry to `b` goes through `a`. Every block
+    /// dominates itself and unreachable blocks take part in no dominance.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        self.core.dominates(a.index(), b.index())
+    }
+
+    pub fn children(&self, id: BlockId) -> Vec<BlockId> {
+        to_ids(self.core.children(id.index())).collect()
+    }
+
+    pub fn frontier(&self, id: BlockId) -> Vec<BlockId> {
+        to_ids(self.core.frontier(id.index())).collect()
+    }
+
+    /// Every block dominating `id`, including itself, in block order
+    pub fn dominators(&self, id: BlockId) -> Vec<BlockId> {
+        (0..self.core.idoms.len())
+            .map(BlockId::from_index)
+            .filter(|dom| self.dominates(*dom, id))
             .collect()
     }
+}
 
-    pub fn compute_frontier(&self, target_label: &Label) -> Vec<Label> {
-        let mut processing_queue: Vec<Label> = self.lookup_node(target_label).successor_labels();
-        let mut frontier = Vec::<Label>::new();
-        let mut processed: HashSet<Label> = HashSet::new();
-        processed.insert(*target_label);
-
-        while let Some(current) = processing_queue.pop() {
-            if self.dominated_map[&current].contains(target_label) {
-                processed.insert(current);
-                for successor_label in self.lookup_node(&current).successor_labels() {
-                    if !processed.contains(&successor_label) {
-                        processing_queue.push(successor_label);
-                    } else if successor_label == *target_label && !frontier.contains(target_label) {
-                        frontier.push(successor_label);
-                    }
+/// Post-dominance over the reversed CFG. Every block ending in a return, or
+/// falling off the end of the function, is given an edge to a single
+/// synthetic exit node which roots the tree. Blocks that can never reach the
+/// exit, such as those in infinite loops, have no post-dominance information.
+pub struct PostDominanceTree {
+    core: DomCore,
+}
+
+impl PostDominanceTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let exit = cfg.ids().count();
+        let leaves_function = |id: BlockId| matches!(cfg[id].link(), Link::Ret | Link::Exit);
+
+        let mut successors: Vec<Vec<usize>> = cfg.ids()
+            .map(|id| cfg.predecessors(id).iter().map(|x| x.index()).collect())
+            .collect();
+        successors.push(cfg.ids().filter(|id| leaves_function(*id)).map(|id| id.index()).collect());
+
+        let mut predecessors: Vec<Vec<usize>> = cfg.ids()
+            .map(|id| {
+                let mut preds: Vec<usize> = cfg.successors(id).iter().map(|x| x.index()).collect();
+                if leaves_function(id) {
+                    preds.push(exit);
                 }
-            } else {
-                frontier.push(current);
-            }
+                preds
+            })
+            .collect();
+        predecessors.push(Vec::new());
+
+        PostDominanceTree {
+            core: DomCore::new(exit, &successors, &predecessors)
+        }
     }
-    // frontier.push(*target_label);
 
-    frontier
+    fn is_exit(&self, node: usize) -> bool {
+        node == self.core.root
     }
-}
 
-fn construct_dominance_tree(nodes: &[Rc<Node>]) -> (HashMap<Label, Vec<Label>>, HashMap<Label, HashSet<Label>>){
-    let dominance_map = determine_dominators(nodes);
-    let mut label_map = HashMap::<Label, Rc<Node>>::new();
-    let mut immediate_dominance_map = HashMap::<Label, Vec<Label>>::new();
+    pub fn reaches_exit(&self, id: BlockId) -> bool {
+        self.core.is_reachable(id.index())
+    }
 
-    for node in nodes {
-        label_map.insert(node.label(), node.clone());
-        immediate_dominance_map.insert(node.label(), Vec::new());
+    /// The closest block post-dominating `id`. This is `None` both when that
+    /// is the synthetic exit and when `id` never reaches the exit.
+    pub fn ipdom(&self, id: BlockId) -> Option<BlockId> {
+        self.core.idom(id.index())
+            .filter(|x| !self.is_exit(*x))
+            .map(BlockId::from_index)
     }
 
+    /// Whether every path from `b` to the exit goes through `a`
+    pub fn post_dominates(&self, a: BlockId, b: BlockId) -> bool {
+        self.core.dominates(a.index(), b.index())
+    }
 
-    for node in nodes {
-        for successor_label in node.successor_refs().iter().map(|x| x.label()) {
-            if dominance_map[&successor_label].contains(&node.label()) && node.label() != successor_label {
-                immediate_dominance_map.get_mut(&node.label()).unwrap().push(successor_label);
+    /// Blocks immediately post-dominated by the synthetic exit
+    pub fn exit_children(&self) -> Vec<BlockId> {
+        to_ids(self.core.children(self.core.root)).collect()
+    }
+
+    pub fn children(&self, id: BlockId) -> Vec<BlockId> {
+        to_ids(self.core.children(id.index())).collect()
+    }
+
+    pub fn frontier(&self, id: BlockId) -> Vec<BlockId> {
+        to_ids(self.core.frontier(id.index())).collect()
+    }
+
+    /// Every block post-dominating `id`, including itself, in block order. The
+    /// synthetic exit is left out.
+    pub fn post_dominators(&self, id: BlockId) -> Vec<BlockId> {
+        (0..self.core.root)
+            .map(BlockId::from_index)
+            .filter(|pdom| self.post_dominates(*pdom, id))
+            .collect()
+    }
+}
+
+/// Control dependence built from post-dominance frontiers: a block depends on
+/// each branch that decides whether it runs. Blocks that run whenever the
+/// function does depend on nothing.
+pub struct ControlDependenceGraph {
+    controllers: Vec<Vec<BlockId>>,
+    dependents: Vec<Vec<BlockId>>,
+}
+
+impl ControlDependenceGraph {
+    pub fn new(cfg: &Cfg) -> Self {
+        Self::from_post_dominance(cfg, &PostDominanceTree::new(cfg))
+    }
+
+    pub fn from_post_dominance(cfg: &Cfg, pdom_tree: &PostDominanceTree) -> Self {
+        let controllers: Vec<Vec<BlockId>> = cfg.ids().map(|id| pdom_tree.frontier(id)).collect();
+        let mut dependents = vec![Vec::new(); controllers.len()];
+        for (id, controlling) in cfg.ids().zip(controllers.iter()) {
+            for controller in controlling {
+                dependents[controller.index()].push(id);
             }
         }
-    }
 
-//     for (label, doms) in dominance_map.iter() {
-//         eprint!("{} is dominated by [", label);
-//         for label in doms.iter() {
-//             eprint!(" {}", label)
-//         }
-//         eprintln!("]");
-//    }
+        ControlDependenceGraph { controllers, dependents }
+    }
 
-    // for (label, doms) in immediate_dominance_map.iter() {
-    //      eprintln!("{} dominates {:?}", label, doms);
-    // }
+    /// The blocks whose branches decide whether `id` runs
+    pub fn controllers(&self, id: BlockId) -> &[BlockId] {
+        &self.controllers[id.index()]
+    }
 
-    (immediate_dominance_map, dominance_map)
+    /// The blocks whose execution depends on the branch ending `id`
+    pub fn dependents(&self, id: BlockId) -> &[BlockId] {
+        &self.dependents[id.index()]
+    }
 }
@@ -0,0 +1,99 @@
+use super::cfg::Cfg;
+use super::dominance::DominanceTree;
+use super::lvn::Table;
+use super::ssa::is_ssa;
+use crate::serde_structs::structs::{FnHeaders, Instr, Label, Op, Var};
+use std::collections::HashMap;
+
+// Numbers the phis of a block. A phi whose arguments other than itself all
+// have the same value has that value too, and two phis taking the same values
+// from the same predecessors are the same. Arguments coming along back edges
+// haven't been numbered yet, so they are only ever equal to themselves.
+fn number_phis(tbl: &mut Table, renames: &HashMap<Var, Var>, instrs: &[Instr]) {
+    let mut seen: HashMap<Vec<(Label, Var)>, Var> = HashMap::new();
+
+    for instr in instrs.iter() {
+        if let Instr::Value { op: Op::Phi, dest, args, labels, .. } = instr {
+            let mut incoming: Vec<(Label, Var)> = labels.iter().copied()
+                .zip(args.iter().map(|x| *renames.get(x).unwrap_or(x)))
+                .collect();
+            incoming.sort();
+
+            let mut values = incoming.iter().map(|(_, x)| *x).filter(|x| x != dest);
+            let same = match values.next() {
+                Some(first) if values.all(|x| x == first) => Some(first),
+                _ => seen.get(&incoming).copied(),
+            };
+
+            if let Some(var) = same {
+                let num = tbl.env_nofail(&var);
+                tbl.set_env(dest, num);
+            } else {
+                seen.insert(incoming, *dest);
+                tbl.insert_unknown(*dest);
+            }
+        }
+    }
+}
+
+/// Global value numbering for functions in SSA form. The value table of LVN
+/// is carried down the dominator tree, so each block starts with the values
+/// of the blocks dominating it and a computation already done in one of them
+/// is replaced by its earlier result. Folding and the other simplifications
+/// are the same as in LVN.
+///
+/// Redundant instructions are left in place for dead code elimination to
+/// remove once nothing refers to them. Functions not in SSA form are left
+/// alone.
+pub fn global_value_numbering(cfg: &mut Cfg, args: &[FnHeaders]) {
+    if cfg.is_empty() || !is_ssa(cfg, args) {
+        return;
+    }
+
+    let dom_tree = DominanceTree::new(cfg);
+    let mut rpo_number = vec![usize::MAX; cfg.ids().count()];
+    for (idx, id) in cfg.reverse_post_order().into_iter().enumerate() {
+        rpo_number[id.index()] = idx;
+    }
+
+    // A renamed value is defined in a block dominating the original, so the
+    // new name can be used anywhere the old one was, phis included
+    let mut renames: HashMap<Var, Var> = HashMap::new();
+    let mut stack = vec![(dom_tree.root(), Table::new())];
+
+    // Children are visited in reverse post-order, which puts every block
+    // after its predecessors other than those along back edges
+    while let Some((id, mut tbl)) = stack.pop() {
+        number_phis(&mut tbl, &renames, &cfg[id].contents.0);
+        for instr in cfg[id].contents.iter_mut() {
+            if !matches!(instr, Instr::Value { op: Op::Phi, .. }) {
+                tbl.rewrite(instr);
+            }
+            if let Some(dest) = instr.dest() {
+                if let Some(var) = tbl.canonical(&dest).filter(|x| *x != dest) {
+                    renames.insert(dest, var);
+                }
+            }
+        }
+        let mut children = dom_tree.children(id);
+        children.sort_by_key(|x| std::cmp::Reverse(rpo_number[x.index()]));
+        for child in children {
+            stack.push((child, tbl.clone()));
+        }
+    }
+
+    // Arguments along back edges come from blocks numbered after the phi, so
+    // phis are only renamed once every block has been seen
+    let ids: Vec<_> = cfg.ids().collect();
+    for id in ids {
+        for instr in cfg[id].contents.iter_mut() {
+            if let Instr::Value { op: Op::Phi, args, .. } = instr {
+                for arg in args.iter_mut() {
+                    if let Some(var) = renames.get(arg) {
+                        *arg = *var;
+                    }
+                }
+            }
+        }
+    }
+}
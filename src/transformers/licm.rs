@@ -0,0 +1,191 @@
+use super::cfg::{BlockId, Cfg};
+use super::dominance::DominanceTree;
+use super::loop_simplify::insert_preheader;
+use super::ssa::is_ssa;
+use crate::analysis::{self, Loop, LoopForest};
+use crate::analysis::reaching_defns::VarDef;
+use crate::serde_structs::structs::{FnHeaders, Instr, Interner, Op, Var};
+use std::collections::{HashMap, HashSet};
+
+// An instruction, given by its block and position within that block
+type Site = (BlockId, usize);
+
+fn instr_args(instr: &Instr) -> &[Var] {
+    match instr {
+        Instr::Value { args, .. } | Instr::Effect { args, .. } => args,
+        _ => &[],
+    }
+}
+
+fn is_candidate(instr: &Instr) -> bool {
+    match instr {
+        Instr::Const { .. } => true,
+        Instr::Value { op, .. } => op.is_pure(),
+        _ => false,
+    }
+}
+
+fn can_fail(instr: &Instr) -> bool {
+    matches!(instr, Instr::Value { op: Op::Div, .. })
+}
+
+// Where the variables used by a loop are defined, which decides whether an
+// argument holds the same value on every iteration
+enum Definitions {
+    Ssa(HashMap<Var, Site>),
+    Reaching(Vec<HashSet<VarDef>>),
+}
+
+impl Definitions {
+    fn new(cfg: &Cfg, args: &[FnHeaders], ssa: bool) -> Self {
+        if ssa {
+            let mut sites = HashMap::new();
+            for id in cfg.ids() {
+                for (idx, instr) in cfg[id].contents.iter().enumerate() {
+                    if let Some(dest) = instr.dest() {
+                        sites.insert(dest, (id, idx));
+                    }
+                }
+            }
+            Definitions::Ssa(sites)
+        } else {
+            let reaching = analysis::reaching_definitions(cfg, args);
+            Definitions::Reaching(reaching.into_iter().map(|x| x.in_data).collect())
+        }
+    }
+
+    /// The definitions of `var` inside the loop that reach its use at `site`,
+    /// or `None` if some definition from outside the loop reaches it too
+    fn in_loop(&self, cfg: &Cfg, natural_loop: &Loop, var: Var, site: Site) -> Option<Vec<Site>> {
+        match self {
+            Definitions::Ssa(sites) => match sites.get(&var) {
+                Some(def) if natural_loop.contains(def.0) => Some(vec![*def]),
+                _ => Some(Vec::new()),
+            },
+            Definitions::Reaching(reaching) => {
+                let (id, idx) = site;
+                let block = &cfg[id].contents.0;
+                if let Some(local) = (0..idx).rev().find(|x| block[*x].dest() == Some(var)) {
+                    return Some(vec![(id, local)]);
+                }
+
+                let mut defs = Vec::new();
+                let mut outside = false;
+                for VarDef(_, num) in reaching[id.index()].iter().filter(|x| x.0 == var) {
+                    // 0 is the function arguments and block `n` is numbered `n + 1`
+                    if *num == 0 || !natural_loop.contains(BlockId::from_index(num - 1)) {
+                        outside = true;
+                        continue;
+                    }
+                    let def_block = BlockId::from_index(num - 1);
+                    let contents = &cfg[def_block].contents.0;
+                    let last = (0..contents.len()).rev().find(|x| contents[*x].dest() == Some(var)).unwrap();
+                    defs.push((def_block, last));
+                }
+                if outside && !defs.is_empty() {
+                    None
+                } else {
+                    Some(defs)
+                }
+            }
+        }
+    }
+}
+
+/// The instructions of `natural_loop` that can move to its preheader, in an
+/// order that keeps each one after those it depends on.
+///
+/// An instruction is hoisted when it is pure and its arguments are either
+/// defined only outside the loop or by a single hoisted instruction. The move
+/// must also be safe: its block dominates every block leaving the loop, or its
+/// result is dead once the loop is left, and for programs not in SSA form it
+/// has to be the only definition in the loop of a variable that isn't live
+/// into the header. In SSA form every use is already dominated by the
+/// definition, so only `div`, which can fail, needs the dominance check.
+fn hoistable(cfg: &Cfg, natural_loop: &Loop, dom_tree: &DominanceTree, args: &[FnHeaders], ssa: bool) -> Vec<Site> {
+    let definitions = Definitions::new(cfg, args, ssa);
+    let live = analysis::live_variables(cfg);
+
+    let mut loop_defs: HashMap<Var, usize> = HashMap::new();
+    for id in natural_loop.body.iter() {
+        for dest in cfg[*id].contents.iter().filter_map(Instr::dest) {
+            *loop_defs.entry(dest).or_insert(0) += 1;
+        }
+    }
+
+    let is_safe = |id: BlockId, instr: &Instr| -> bool {
+        let dest = instr.dest().unwrap();
+        let dominates_exits = natural_loop.exiting.iter().all(|x| dom_tree.dominates(id, *x));
+        if ssa {
+            return dominates_exits || !can_fail(instr);
+        }
+        let dead_outside = natural_loop.exits.iter().all(|x| !live[x.index()].in_data.contains(&dest));
+        loop_defs[&dest] == 1
+            && !live[natural_loop.header.index()].in_data.contains(&dest)
+            && (dominates_exits || (dead_outside && !can_fail(instr)))
+    };
+
+    let mut hoisted: Vec<Site> = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in natural_loop.body.iter().copied() {
+            for (idx, instr) in cfg[id].contents.iter().enumerate() {
+                if !is_candidate(instr) || hoisted.contains(&(id, idx)) {
+                    continue;
+                }
+                let invariant = instr_args(instr).iter().all(|arg| {
+                    match definitions.in_loop(cfg, natural_loop, *arg, (id, idx)) {
+                        Some(defs) => defs.is_empty() || (defs.len() == 1 && hoisted.contains(&defs[0])),
+                        None => false,
+                    }
+                });
+                if invariant && is_safe(id, instr) {
+                    hoisted.push((id, idx));
+                    changed = true;
+                }
+            }
+        }
+    }
+    hoisted
+}
+
+fn hoist(cfg: &mut Cfg, natural_loop: &Loop, sites: Vec<Site>, names: &mut Interner) {
+    let mut by_position = sites.clone();
+    by_position.sort();
+    let mut removed: HashMap<Site, Instr> = HashMap::new();
+    for (id, idx) in by_position.into_iter().rev() {
+        removed.insert((id, idx), cfg[id].contents.0.remove(idx));
+    }
+
+    let instrs = sites.iter().map(|x| removed.remove(x).unwrap()).collect();
+    insert_preheader(cfg, natural_loop, instrs, names);
+}
+
+/// Moves loop-invariant computations out of every loop, innermost loops
+/// first so that what they hoist can be hoisted again by the loops around
+/// them. Loops headed by the entry block are left alone since the entry
+/// can't be given a preheader.
+pub fn loop_invariant_code_motion(cfg: &mut Cfg, args: &[FnHeaders], names: &mut Interner) {
+    if cfg.is_empty() {
+        return;
+    }
+    let ssa = is_ssa(cfg, args);
+
+    let headers = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+
+    // Block ids stay valid throughout since preheaders are only ever appended
+    for header in headers {
+        if header == cfg.entry() {
+            continue;
+        }
+        let dom_tree = DominanceTree::new(cfg);
+        let forest = LoopForest::new(cfg, &dom_tree);
+        let natural_loop = forest.loops().iter().find(|x| x.header == header).unwrap();
+
+        let sites = hoistable(cfg, natural_loop, &dom_tree, args, ssa);
+        if !sites.is_empty() {
+            hoist(cfg, natural_loop, sites, names);
+        }
+    }
+}
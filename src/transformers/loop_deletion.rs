@@ -0,0 +1,118 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::dominance::DominanceTree;
+use super::loop_simplify::insert_preheader;
+use crate::analysis::{self, Loop, LoopForest};
+use crate::analysis::induction::{constant_int, induction_variables};
+use crate::serde_structs::structs::{Instr, Interner, Op};
+
+// Nothing in the loop can be seen from outside it or make the program fail,
+// other than through the variables it defines
+fn has_no_effects(cfg: &Cfg, natural_loop: &Loop) -> bool {
+    natural_loop.body.iter().all(|id| {
+        cfg[*id].contents.iter().all(|instr| match instr {
+            Instr::Label { .. } | Instr::Const { .. } => true,
+            Instr::Value { op, .. } => op.is_pure() && *op != Op::Div,
+            Instr::Effect { op, .. } => matches!(op, Op::Jmp | Op::Br | Op::Nop),
+        })
+    })
+}
+
+/// Whether `exiting` leaves the loop once a basic induction variable moving
+/// by a constant step passes a loop-invariant bound. The test and the update
+/// must both dominate every latch so they happen on every iteration, and the
+/// step must head towards the bound without being able to wrap around past
+/// it. An unknown bound is taken to be the furthest it could be.
+fn exits_in_time(cfg: &Cfg, natural_loop: &Loop, dom_tree: &DominanceTree, exiting: BlockId) -> bool {
+    let dominates_latches = |id: BlockId| natural_loop.latches.iter().all(|x| dom_tree.dominates(id, *x));
+    if !dominates_latches(exiting) {
+        return false;
+    }
+
+    let block = &cfg[exiting].contents.0;
+    let (cond, continue_on_true) = match (block.last(), cfg[exiting].link()) {
+        (Some(Instr::Effect { op: Op::Br, args, .. }), Link::Branch { true_branch, false_branch })
+            if natural_loop.contains(true_branch) != natural_loop.contains(false_branch) => {
+            (args[0], natural_loop.contains(true_branch))
+        }
+        _ => return false,
+    };
+    let (op, args) = match block.iter().rev().find(|x| x.dest() == Some(cond)) {
+        Some(Instr::Value { op: op @ (Op::Lt | Op::Le | Op::Gt | Op::Ge), args, .. }) => (*op, args),
+        _ => return false,
+    };
+
+    let inductions = induction_variables(cfg, natural_loop);
+    let (basic, bound, op) = match (inductions.basic_var(args[0]), inductions.basic_var(args[1])) {
+        (Some(basic), None) => (basic, args[1], op),
+        (None, Some(basic)) => (basic, args[0], op.swapped().unwrap()),
+        _ => return false,
+    };
+    let defined_in_loop = natural_loop.body.iter()
+        .any(|id| cfg[*id].contents.iter().any(|x| x.dest() == Some(bound)));
+    if defined_in_loop || !dominates_latches(basic.site.0) {
+        return false;
+    }
+    // The loop carries on while `basic op bound`
+    let op = if continue_on_true { op } else { op.negated().unwrap() };
+
+    let step = match constant_int(cfg, basic.step) {
+        Some(step) if basic.decreasing => -(step as i128),
+        Some(step) => step as i128,
+        None => return false,
+    };
+    let (min, max) = (i64::MIN as i128, i64::MAX as i128);
+    let bound = constant_int(cfg, bound).map(|x| x as i128);
+    // The furthest the variable can get from one step while the test passes
+    match op {
+        Op::Lt => step > 0 && bound.unwrap_or(max) - 1 + step <= max,
+        Op::Le => step > 0 && bound.unwrap_or(max) + step <= max,
+        Op::Gt => step < 0 && bound.unwrap_or(min) + 1 + step >= min,
+        _ => step < 0 && bound.unwrap_or(min) + step >= min,
+    }
+}
+
+fn is_dead(cfg: &Cfg, natural_loop: &Loop, dom_tree: &DominanceTree) -> bool {
+    if !natural_loop.children.is_empty() || natural_loop.exits.len() != 1 || !has_no_effects(cfg, natural_loop) {
+        return false;
+    }
+
+    let live = analysis::live_variables(cfg);
+    let live_out = &live[natural_loop.exits[0].index()].in_data;
+    let used_after = natural_loop.body.iter()
+        .any(|id| cfg[*id].contents.iter().filter_map(Instr::dest).any(|x| live_out.contains(&x)));
+
+    !used_after && natural_loop.exiting.iter().any(|x| exits_in_time(cfg, natural_loop, dom_tree, *x))
+}
+
+/// Removes loops that do nothing visible, never fail and always finish, so
+/// that running them makes no difference. The preheader of each one is sent
+/// straight to the loop's only exit. Loops are considered innermost first,
+/// and only once the loops inside them are gone. Functions with phis are
+/// left alone, as are loops headed by the entry block.
+pub fn delete_loops(cfg: &mut Cfg, names: &mut Interner) {
+    if cfg.is_empty() || cfg.has_phis() {
+        return;
+    }
+
+    let headers = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+
+    // Block ids stay valid until the deleted loops are removed at the end,
+    // since new blocks are only ever appended
+    for header in headers {
+        if header == cfg.entry() {
+            continue;
+        }
+        let dom_tree = DominanceTree::new(cfg);
+        let forest = LoopForest::new(cfg, &dom_tree);
+        let natural_loop = match forest.loops().iter().find(|x| x.header == header) {
+            Some(natural_loop) => natural_loop,
+            None => continue,
+        };
+
+        if is_dead(cfg, natural_loop, &dom_tree) {
+            let preheader = insert_preheader(cfg, natural_loop, Vec::new(), names);
+            cfg.redirect(preheader, header, natural_loop.exits[0]);
+        }
+    }
+    cfg.remove_unreachable();
+}
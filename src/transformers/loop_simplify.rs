@@ -0,0 +1,132 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::dominance::DominanceTree;
+use crate::analysis::{Loop, LoopForest};
+use crate::serde_structs::structs::{Instr, Interner, Label, Op, Type, Var};
+
+pub(super) fn outside_predecessors(cfg: &Cfg, natural_loop: &Loop) -> Vec<BlockId> {
+    cfg.predecessors(natural_loop.header).iter()
+        .copied()
+        .filter(|x| !natural_loop.contains(*x))
+        .collect()
+}
+
+/// Adds a block holding `instrs` which the edges from `preds` into `target`
+/// go through instead, ending with a jump to `target`. Phis in `target` then
+/// get their arguments for those edges from the new block, merged by a new
+/// phi when there was more than one.
+fn split_predecessors(cfg: &mut Cfg, target: BlockId, preds: &[BlockId], mut instrs: Vec<Instr>, names: &mut Interner) -> BlockId {
+    let pred_labels: Vec<Label> = preds.iter().map(|x| cfg[*x].label()).collect();
+    let label = names.fresh_label();
+
+    for instr in cfg[target].contents.iter_mut() {
+        if let Instr::Value { op: Op::Phi, dest, r_type, args, labels, .. } = instr {
+            let mut incoming: Vec<(Var, Label)> = Vec::new();
+            let mut idx = 0;
+            while idx < labels.len() {
+                if pred_labels.contains(&labels[idx]) {
+                    incoming.push((args.remove(idx), labels.remove(idx)));
+                } else {
+                    idx += 1;
+                }
+            }
+
+            match incoming.len() {
+                0 => {}
+                1 => {
+                    args.push(incoming[0].0);
+                    labels.push(label);
+                }
+                _ => {
+                    let merged = Var(names.fresh(*dest));
+                    instrs.insert(0, phi(merged, r_type.clone(), incoming));
+                    args.push(merged);
+                    labels.push(label);
+                }
+            }
+        }
+    }
+
+    instrs.push(Instr::Effect {
+        op: Op::Jmp,
+        args: Vec::new(),
+        funcs: Vec::new(),
+        labels: vec![cfg[target].label()],
+    });
+    let block = cfg.add_block(label, instrs, Link::Jump(target));
+    for pred in preds {
+        cfg.redirect(*pred, target, block);
+    }
+    block
+}
+
+fn phi(dest: Var, r_type: Type, incoming: Vec<(Var, Label)>) -> Instr {
+    let (args, labels) = incoming.into_iter().unzip();
+    Instr::Value {
+        op: Op::Phi,
+        dest,
+        r_type,
+        args,
+        funcs: Vec::new(),
+        labels,
+    }
+}
+
+/// Places `instrs` in a block that every edge entering the loop from outside
+/// goes through. A lone outside predecessor leading only to the header is
+/// used as it is; otherwise a new block is added that ends by jumping to the
+/// header.
+pub(super) fn insert_preheader(cfg: &mut Cfg, natural_loop: &Loop, instrs: Vec<Instr>, names: &mut Interner) -> BlockId {
+    let header = natural_loop.header;
+    let outside = outside_predecessors(cfg, natural_loop);
+
+    if let [pred] = outside[..] {
+        if cfg.successors(pred) == [header] {
+            let block = &mut cfg[pred].contents.0;
+            let jumps = matches!(block.last(), Some(Instr::Effect { op, .. }) if op.is_terminator());
+            let end = if jumps { block.len() - 1 } else { block.len() };
+            block.splice(end..end, instrs);
+            return pred;
+        }
+    }
+    split_predecessors(cfg, header, &outside, instrs, names)
+}
+
+// Gives the loop its preheader, a single latch and exit blocks that are only
+// entered from inside the loop, in that order
+fn simplify(cfg: &mut Cfg, natural_loop: &Loop, names: &mut Interner) {
+    let header = natural_loop.header;
+    if header != cfg.entry() {
+        insert_preheader(cfg, natural_loop, Vec::new(), names);
+    }
+
+    if natural_loop.latches.len() > 1 {
+        split_predecessors(cfg, header, &natural_loop.latches, Vec::new(), names);
+    }
+
+    for exit in natural_loop.exits.iter().copied() {
+        let (inside, outside): (Vec<BlockId>, Vec<BlockId>) = cfg.predecessors(exit).iter()
+            .partition(|x| natural_loop.contains(**x));
+        if !outside.is_empty() {
+            split_predecessors(cfg, exit, &inside, Vec::new(), names);
+        }
+    }
+}
+
+/// Puts every loop into a canonical shape, innermost loops first: a single
+/// preheader leading only to the header, a single latch and exit blocks that
+/// are only reached from inside the loop. Loops headed by the entry block
+/// get no preheader, since nothing can come before the entry.
+pub fn loop_simplify(cfg: &mut Cfg, names: &mut Interner) {
+    if cfg.is_empty() {
+        return;
+    }
+
+    let headers = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+
+    // Block ids stay valid throughout since new blocks are only ever appended
+    for header in headers {
+        let forest = LoopForest::new(cfg, &DominanceTree::new(cfg));
+        let natural_loop = forest.loops().iter().find(|x| x.header == header).unwrap();
+        simplify(cfg, natural_loop, names);
+    }
+}
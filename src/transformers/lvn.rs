@@ -1,5 +1,6 @@
-use crate::serde_structs::namer;
-use crate::serde_structs::structs::{Instr, Literal, Op, Var};
+use super::cfg::{BlockId, Cfg};
+use crate::arith;
+use crate::serde_structs::structs::{Instr, Interner, Literal, Op, Type, Var};
 use std::collections::HashMap;
 use std::ops::RangeInclusive;
 
@@ -35,7 +36,9 @@ impl Value {
                 } else if let Op::Not = op {
                     // Unary not on const
                     if let Value::Literal(ref inner) = tbl.get_row_value(*arg) {
-                        *self = Value::Literal(!inner.clone())
+                        if let Some(lit) = fold(Op::Not, std::slice::from_ref(inner)) {
+                            *self = Value::Literal(lit)
+                        }
                     }
                 }
             }
@@ -44,39 +47,8 @@ impl Value {
                 if let (Value::Literal(ref l1), Value::Literal(ref l2)) =
                     (tbl.get_row_value(*a1), tbl.get_row_value(*a2))
                 {
-                    match op {
-                        Op::Add => *self = Value::Literal(l1.clone() + l2.clone()),
-                        Op::Sub => *self = Value::Literal(l1.clone() - l2.clone()),
-                        Op::Mul => *self = Value::Literal(l1.clone() * l2.clone()),
-                        Op::Div => *self = Value::Literal(l1.clone() / l2.clone()),
-                        Op::Eq => {
-                            if let (Literal::Int(ref i1), Literal::Int(ref i2)) = (l1, l2) {
-                                *self = Value::Literal(Literal::Bool(i1 == i2))
-                            }
-                        }
-                        Op::Lt => {
-                            if let (Literal::Int(ref i1), Literal::Int(ref i2)) = (l1, l2) {
-                                *self = Value::Literal(Literal::Bool(i1 < i2))
-                            }
-                        }
-                        Op::Gt => {
-                            if let (Literal::Int(ref i1), Literal::Int(ref i2)) = (l1, l2) {
-                                *self = Value::Literal(Literal::Bool(i1 > i2))
-                            }
-                        }
-                        Op::Le => {
-                            if let (Literal::Int(ref i1), Literal::Int(ref i2)) = (l1, l2) {
-                                *self = Value::Literal(Literal::Bool(i1 <= i2))
-                            }
-                        }
-                        Op::Ge => {
-                            if let (Literal::Int(ref i1), Literal::Int(ref i2)) = (l1, l2) {
-                                *self = Value::Literal(Literal::Bool(i1 >= i2))
-                            }
-                        }
-                        Op::And => *self = Value::Literal(l1.clone() & l2.clone()),
-                        Op::Or => *self = Value::Literal(l1.clone() | l2.clone()),
-                        _ => {}
+                    if let Some(lit) = fold(*op, &[l1.clone(), l2.clone()]) {
+                        *self = Value::Literal(lit)
                     }
                 }
                 // Special
@@ -119,6 +91,15 @@ impl Value {
     }
 }
 
+// Constants that would come out as infinities or NaN are left for the
+// program to compute, since they can't be written as a literal
+fn fold(op: Op, args: &[Literal]) -> Option<Literal> {
+    match arith::evaluate(op, args)? {
+        Literal::Float(x) if !x.is_finite() => None,
+        lit => Some(lit),
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -137,21 +118,22 @@ impl PartialEq for Value {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 struct Row {
     entry_num: LNum,
     value: Value,
     canonical_name: Var,
 }
 
-struct Table {
+#[derive(Clone)]
+pub(super) struct Table {
     next_num: usize,
     rows: Vec<Row>,
     env: HashMap<Var, LNum>,
 }
 
 impl Table {
-    fn new() -> Table {
+    pub(super) fn new() -> Table {
         Table {
             next_num: 0,
             rows: Vec::new(),
@@ -164,14 +146,14 @@ impl Table {
         self.env[var]
     }
 
-    fn env_nofail(&mut self, var: &Var) -> LNum {
+    pub(super) fn env_nofail(&mut self, var: &Var) -> LNum {
         if !self.env.contains_key(var) {
             self.insert_value(Value::Unknown, *var)
         }
         self.env[var]
     }
 
-    fn set_env(&mut self, var: &Var, num: LNum) {
+    pub(super) fn set_env(&mut self, var: &Var, num: LNum) {
         // eprintln!("Set var {:?} equal to row {}", var, num);
         self.env.insert(*var, num);
     }
@@ -180,6 +162,19 @@ impl Table {
         self.rows[num].canonical_name
     }
 
+    /// The name `var`'s value is best referred to by, if it has one yet
+    pub(super) fn canonical(&self, var: &Var) -> Option<Var> {
+        if self.env.contains_key(var) {
+            Some(self.rewrite_var(var))
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn insert_unknown(&mut self, var: Var) {
+        self.insert_value(Value::Unknown, var)
+    }
+
     fn rewrite_var(&self, var: &Var) -> Var {
         let line = self.env(var);
         if let Value::UnaryOp(Op::Id, num) = self.get_row_value(line) {
@@ -198,32 +193,6 @@ impl Table {
     }
 
     fn insert_value(&mut self, val: Value, name: Var) {
-        let mut overwrite = false;
-        let mut broke = false;
-        if let Some(idx) = self.env.get(&name) {
-            if let Value::Unknown = self.get_row_value(*idx) {
-                for row in self.rows.iter() {
-                    match row.value {
-                        Value::UnaryOp(Op::Id, ind) if ind == *idx => {
-                            broke = true;
-                            break;
-                        }
-                        _ => {}
-                    }
-
-                }
-                overwrite = !broke;
-            } else {
-                panic!("Defined name twice! {}", name);
-            }
-        }
-
-        if overwrite {
-            let row_idx = self.env.get(&name).unwrap();
-            self.rows[*row_idx].value = val;
-            return;
-        }
-
         let new_row = Row {
             entry_num: self.next_num,
             value: val,
@@ -234,6 +203,33 @@ impl Table {
         self.rows.push(new_row);
     }
 
+    // Called when `var` is about to be given a new value. Rows it was the
+    // name of are handed to another variable holding the same value, or if
+    // there is none, can no longer be matched or copied from.
+    fn forget(&mut self, var: &Var) {
+        let num = match self.env.remove(var) {
+            Some(num) => num,
+            None => return,
+        };
+        if self.rows[num].canonical_name != *var {
+            return;
+        }
+
+        let other = self.env.iter().filter(|(_, x)| **x == num).map(|(x, _)| *x).min();
+        if let Some(other) = other {
+            self.rows[num].canonical_name = other;
+        } else {
+            self.rows[num].value = Value::Unknown;
+            for row in self.rows.iter_mut() {
+                if let Value::UnaryOp(Op::Id, arg) = row.value {
+                    if arg == num {
+                        row.value = Value::Unknown;
+                    }
+                }
+            }
+        }
+    }
+
     fn lookup_value(&self, val: &Value) -> Option<(LNum, Var)> {
         for row in self.rows.iter() {
             if *val == row.value {
@@ -249,7 +245,19 @@ impl Table {
 
     fn generate_value(&mut self, instr: &mut Instr) -> Option<Value> {
         match instr {
+            // A float constant may have been written as an integer
+            Instr::Const { value: Literal::Int(i), r_type: Type::Float, .. } => {
+                Some(Value::Literal(Literal::Float(*i as f64)))
+            }
             Instr::Const { value, .. } => Some(Value::Literal(value.clone())),
+            // Memory operations and phis can give different results for the
+            // same arguments, which still need to be known to the table
+            Instr::Value { op, args, .. } if !op.is_pure() && *op != Op::Call => {
+                for arg in args.iter() {
+                    self.env_nofail(arg);
+                }
+                Some(Value::Unknown)
+            }
             Instr::Value { op, args, .. } | Instr::Effect { op, args, .. } => {
                 if *op == Op::Call {
                     let args = args.iter().map(|x| self.env_nofail(x)).collect();
@@ -272,7 +280,14 @@ impl Table {
                             v.transform_special_form(self);
                             Some(v)
                         }
-                        _ => panic!("Operation with {} args! {:?}", args.len(), args),
+                        _ => {
+                            // Only effects like print take more arguments,
+                            // which just need to be known to the table
+                            for arg in args.iter() {
+                                self.env_nofail(arg);
+                            }
+                            None
+                        }
                     }
                 }
             }
@@ -280,62 +295,38 @@ impl Table {
         }
     }
 
-    fn rewrite(&mut self, instr: &mut Instr) {
+    pub(super) fn rewrite(&mut self, instr: &mut Instr) {
         let v = self.generate_value(instr);
         // eprintln!("{:?}", v);
 
+        // Arguments are renamed before the destination gets its new value,
+        // since it may be one of them
         match instr {
-            Instr::Const { dest, .. } => {
-                if let Some(v) = v {
-                    let linenum = self.lookup_value(&v);
-                    if let Some((num, _var)) = linenum {
-                        // eprintln!("Value for {:?} is already present as {:?}", dest, var);
-                        self.set_env(dest, num);
-                    } else {
-                        // eprintln!("Value for {:?} is not present", dest);
-                        self.insert_value(v, *dest);
-                    }
-                }
-            }
             Instr::Value {
                 op,
                 args,
                 dest,
                 r_type,
                 ..
-            } => {
-                if let Some(ref v) = v {
-                    let linenum = self.lookup_value(&v);
-
-                    if let Some((num, _var)) = linenum {
-                        // eprintln!("Value for {:?} is already present as {:?}", dest, var);
-                        self.set_env(dest, num);
-                    } else {
-                        // eprintln!("Value for {:?} is not present", dest);
-                        self.insert_value(v.clone(), *dest);
+            } => match v {
+                Some(Value::Literal(ref lit)) => {
+                    *instr = Instr::Const {
+                        op: Op::Const,
+                        dest: *dest,
+                        r_type: r_type.clone(),
+                        value: lit.clone(),
                     }
                 }
-
-                match v {
-                    Some(Value::Literal(lit)) => {
-                        *instr = Instr::Const {
-                            op: Op::Const,
-                            dest: *dest,
-                            r_type: r_type.clone(),
-                            value: lit,
-                        }
-                    }
-                    Some(Value::UnaryOp(new_op, new_arg)) => {
-                        *op = new_op;
-                        *args = vec![self.get_var(new_arg)]
-                    }
-                    _ => {
-                        for arg in args.iter_mut() {
-                            *arg = self.rewrite_var(arg)
-                        }
+                Some(Value::UnaryOp(new_op, new_arg)) => {
+                    *op = new_op;
+                    *args = vec![self.get_var(new_arg)]
+                }
+                _ => {
+                    for arg in args.iter_mut() {
+                        *arg = self.rewrite_var(arg)
                     }
                 }
-            }
+            },
             Instr::Effect { args, .. } => {
                 for arg in args.iter_mut() {
                     *arg = self.rewrite_var(arg)
@@ -343,12 +334,24 @@ impl Table {
             }
             _ => {}
         }
+
+        if let Some(dest) = instr.dest() {
+            let v = v.unwrap_or(Value::Unknown);
+            self.forget(&dest);
+            if let Some((num, _var)) = self.lookup_value(&v) {
+                // eprintln!("Value for {:?} is already present as {:?}", dest, var);
+                self.set_env(&dest, num);
+            } else {
+                // eprintln!("Value for {:?} is not present", dest);
+                self.insert_value(v, dest);
+            }
+        }
     }
 }
 
-pub fn run_lvn(instrs: &mut Vec<Instr>) {
+pub fn run_lvn(instrs: &mut Vec<Instr>, names: &mut Interner) {
     // eprintln!("{:?}", instrs);
-    force_unique_names(instrs);
+    force_unique_names(instrs, names);
     // for instr in instrs.iter(){
     //     eprintln!("{}", instr);
     // }
@@ -361,8 +364,42 @@ pub fn run_lvn(instrs: &mut Vec<Instr>) {
     }
 }
 
-fn force_unique_names(instrs: &mut Vec<Instr>) {
-    let name_reader = namer();
+/// LVN over extended basic blocks, the trees of blocks formed by following
+/// edges into blocks that have no other predecessor. Each block starts with
+/// the table left at the end of its predecessor in the tree, so values
+/// computed before a branch are reused on either side of it. Blocks that can
+/// be reached from more than one place start again with an empty table.
+pub fn run_superlocal_lvn(cfg: &mut Cfg, names: &mut Interner) {
+    if cfg.is_empty() {
+        return;
+    }
+
+    let entry = cfg.entry();
+    let roots: Vec<BlockId> = cfg.ids()
+        .filter(|x| *x == entry || cfg.predecessors(*x).len() != 1)
+        .collect();
+
+    for root in roots {
+        let mut stack = vec![(root, Table::new())];
+        while let Some((id, mut tbl)) = stack.pop() {
+            let instrs = &mut cfg[id].contents.0;
+            force_unique_names(instrs, names);
+            for instr in instrs.iter_mut() {
+                tbl.rewrite(instr)
+            }
+
+            let mut successors = cfg.successors(id);
+            successors.dedup();
+            for successor in successors {
+                if successor != entry && cfg.predecessors(successor).len() == 1 {
+                    stack.push((successor, tbl.clone()));
+                }
+            }
+        }
+    }
+}
+
+fn force_unique_names(instrs: &mut Vec<Instr>, names: &mut Interner) {
     let mut new_mapping = HashMap::<Var, Vec<(RangeInclusive<usize>, Var)>>::new();
     let mut prev_defn = HashMap::<Var, usize>::new();
 
@@ -371,7 +408,7 @@ fn force_unique_names(instrs: &mut Vec<Instr>) {
             Instr::Const { dest, .. } | Instr::Value { dest, .. } => {
                 if prev_defn.contains_key(dest) && new_mapping.contains_key(dest) {
                     let prior_marker = prev_defn.remove(dest).unwrap();
-                    let fresh = name_reader.fresh(&dest.0);
+                    let fresh = names.fresh(*dest);
 
                     new_mapping
                         .get_mut(dest)
@@ -380,7 +417,7 @@ fn force_unique_names(instrs: &mut Vec<Instr>) {
                     prev_defn.insert(*dest, idx);
                 } else if prev_defn.contains_key(dest) {
                     let prior_marker = prev_defn.remove(dest).unwrap();
-                    let fresh = name_reader.fresh(&dest.0);
+                    let fresh = names.fresh(*dest);
 
                     let new_list = vec![(prior_marker..=idx, Var(fresh))];
                     new_mapping.insert(*dest, new_list);
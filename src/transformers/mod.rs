@@ -5,3 +5,15 @@ pub mod lvn;
 pub mod config;
 pub mod dominance;
 pub mod ssa;
+pub mod loop_simplify;
+pub mod licm;
+pub mod strength;
+pub mod unroll;
+pub mod rotate;
+pub mod unswitch;
+pub mod loop_deletion;
+pub mod cprop;
+pub mod sccp;
+pub mod gvn;
+pub mod copyprop;
+pub mod passes;
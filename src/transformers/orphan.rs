@@ -1,7 +1,5 @@
-use super::cfg::{Node};
-use std::rc::Rc;
+use super::cfg::Cfg;
 
-pub fn remove_inaccessible_blocks(blocks: Vec<Rc<Node>>) -> Vec<Rc<Node>> {
-    let _root = Rc::downgrade(&blocks[0]);
-    (blocks).into_iter().filter(|x| {Rc::weak_count(x) > x.successor_count()}).collect()
+pub fn remove_inaccessible_blocks(cfg: &mut Cfg) {
+    cfg.remove_unreachable()
 }
@@ -0,0 +1,250 @@
+use crate::serde_structs::structs::{CFGFunction, CFGProgram, Function, Interner, Program};
+
+/// A transformation over a single function. `F` is the representation the
+/// pass works on, either the linear `Function` or the `CFGFunction`. Any new
+/// names a pass needs come from the program's interner.
+pub trait Pass<F> {
+    fn run(&self, fun: &mut F, names: &mut Interner);
+}
+
+pub enum Stage {
+    Linear(Box<dyn Pass<Function>>),
+    Cfg(Box<dyn Pass<CFGFunction>>),
+}
+
+// The program is only converted between forms when the next pass needs the
+// other one, so runs of CFG passes share a single CFG construction
+enum ProgramForm {
+    Linear(Program),
+    Cfg(CFGProgram),
+}
+
+impl ProgramForm {
+    fn into_linear(self) -> Program {
+        match self {
+            ProgramForm::Linear(prog) => prog,
+            ProgramForm::Cfg(cfg) => cfg.make_serializeable(),
+        }
+    }
+
+    fn into_cfg(self) -> CFGProgram {
+        match self {
+            ProgramForm::Linear(prog) => prog.determine_cfg(),
+            ProgramForm::Cfg(cfg) => cfg,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PassManager {
+    stages: Vec<Stage>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        PassManager { stages: Vec::new() }
+    }
+
+    pub fn add(&mut self, stage: Stage) {
+        self.stages.push(stage)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Runs every stage in the order it was added, each over all functions
+    /// of the program before moving on to the next.
+    pub fn run(&self, prog: Program) -> Program {
+        let mut form = ProgramForm::Linear(prog);
+
+        for stage in self.stages.iter() {
+            form = match stage {
+                Stage::Linear(pass) => {
+                    let mut prog = form.into_linear();
+                    for fun in prog.functions.iter_mut() {
+                        pass.run(fun, &mut prog.names)
+                    }
+                    ProgramForm::Linear(prog)
+                }
+                Stage::Cfg(pass) => {
+                    let mut cfg = form.into_cfg();
+                    for fun in cfg.functions.iter_mut() {
+                        pass.run(fun, &mut cfg.names)
+                    }
+                    ProgramForm::Cfg(cfg)
+                }
+            }
+        }
+
+        form.into_linear()
+    }
+}
+
+pub struct GlobalTdce;
+
+impl Pass<Function> for GlobalTdce {
+    fn run(&self, fun: &mut Function, _names: &mut Interner) {
+        fun.g_tcde()
+    }
+}
+
+pub struct LocalTdce;
+
+impl Pass<CFGFunction> for LocalTdce {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_basic_dce()
+    }
+}
+
+pub struct GlobalDce;
+
+impl Pass<CFGFunction> for GlobalDce {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_live_dce()
+    }
+}
+
+pub struct OrphanBlocks;
+
+impl Pass<CFGFunction> for OrphanBlocks {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.drop_orphan_blocks()
+    }
+}
+
+pub struct Lvn;
+
+impl Pass<CFGFunction> for Lvn {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_lvn(names)
+    }
+}
+
+pub struct SuperlocalLvn;
+
+impl Pass<CFGFunction> for SuperlocalLvn {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_superlocal_lvn(names)
+    }
+}
+
+pub struct Cprop;
+
+impl Pass<CFGFunction> for Cprop {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_cprop()
+    }
+}
+
+pub struct CopyProp;
+
+impl Pass<CFGFunction> for CopyProp {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_copyprop()
+    }
+}
+
+pub struct Sccp;
+
+impl Pass<CFGFunction> for Sccp {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_sccp()
+    }
+}
+
+pub struct Gvn;
+
+impl Pass<CFGFunction> for Gvn {
+    fn run(&self, fun: &mut CFGFunction, _names: &mut Interner) {
+        fun.apply_gvn()
+    }
+}
+
+pub struct ToSsa;
+
+impl Pass<CFGFunction> for ToSsa {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        // The dominance computation needs every block to be reachable
+        fun.drop_orphan_blocks();
+        fun.to_ssa(names)
+    }
+}
+
+pub struct FromSsa;
+
+impl Pass<CFGFunction> for FromSsa {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.from_ssa(names)
+    }
+}
+
+pub struct Licm;
+
+impl Pass<CFGFunction> for Licm {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_licm(names)
+    }
+}
+
+pub struct LoopSimplify;
+
+impl Pass<CFGFunction> for LoopSimplify {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_loop_simplify(names)
+    }
+}
+
+pub struct Rotate;
+
+impl Pass<CFGFunction> for Rotate {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_rotate(names)
+    }
+}
+
+pub struct StrengthReduce;
+
+impl Pass<CFGFunction> for StrengthReduce {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_strength_reduction(names)
+    }
+}
+
+pub struct Unroll {
+    pub factor: usize,
+}
+
+impl Pass<CFGFunction> for Unroll {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_unroll(self.factor, names)
+    }
+}
+
+pub struct Peel {
+    pub count: usize,
+}
+
+impl Pass<CFGFunction> for Peel {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_peel(self.count, names)
+    }
+}
+
+pub struct Unswitch {
+    pub budget: usize,
+}
+
+impl Pass<CFGFunction> for Unswitch {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_unswitch(self.budget, names)
+    }
+}
+
+pub struct DeleteLoops;
+
+impl Pass<CFGFunction> for DeleteLoops {
+    fn run(&self, fun: &mut CFGFunction, names: &mut Interner) {
+        fun.apply_loop_deletion(names)
+    }
+}
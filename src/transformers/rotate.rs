@@ -0,0 +1,51 @@
+use super::cfg::{Cfg, Link};
+use super::dominance::DominanceTree;
+use super::loop_simplify::outside_predecessors;
+use crate::analysis::{Loop, LoopForest};
+use crate::serde_structs::structs::Interner;
+
+/// Turns a loop that tests whether to carry on at the top into one that tests
+/// at the bottom. Edges entering the loop go to a copy of the header instead,
+/// which guards the first iteration, and the original header is left as the
+/// test the latches run into. Its successor inside the loop becomes the new
+/// header.
+fn rotate(cfg: &mut Cfg, natural_loop: &Loop, names: &mut Interner) {
+    let header = natural_loop.header;
+    if natural_loop.latches.contains(&header) {
+        return;
+    }
+    match cfg[header].link() {
+        Link::Branch { true_branch, false_branch }
+            if natural_loop.contains(true_branch) != natural_loop.contains(false_branch) => {}
+        _ => return,
+    }
+
+    let outside = outside_predecessors(cfg, natural_loop);
+    let guard = cfg.duplicate(&[header], names)[&header];
+    for pred in outside {
+        cfg.redirect(pred, header, guard);
+    }
+}
+
+/// Rotates every loop whose header decides whether the loop is left, innermost
+/// loops first. Functions with phis are left alone, as are loops headed by the
+/// entry block, since that can't be moved out of the way for a guard.
+pub fn rotate_loops(cfg: &mut Cfg, names: &mut Interner) {
+    if cfg.is_empty() || cfg.has_phis() {
+        return;
+    }
+
+    let headers = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+
+    // Rotating a loop leaves the headers of the loops around it as they were,
+    // and blocks are only ever appended so the ids stay valid
+    for header in headers {
+        if header == cfg.entry() {
+            continue;
+        }
+        let forest = LoopForest::new(cfg, &DominanceTree::new(cfg));
+        if let Some(natural_loop) = forest.loops().iter().find(|x| x.header == header) {
+            rotate(cfg, natural_loop, names);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::ssa::is_ssa;
+use crate::analysis::cprop::{evaluate, Value};
+use crate::serde_structs::structs::{FnHeaders, Instr, Label, Literal, Op, Var};
+use std::collections::{HashMap, HashSet};
+
+// An instruction, given by its block and position within that block
+type Site = (BlockId, usize);
+
+/// The state of sparse conditional constant propagation over a function in
+/// SSA form. Variables missing from `values` haven't been given a value yet,
+/// either because their definition hasn't been reached or because it depends
+/// on something that hasn't.
+struct Sccp<'a> {
+    cfg: &'a Cfg,
+    labels: HashMap<Label, BlockId>,
+    uses: HashMap<Var, Vec<Site>>,
+    values: HashMap<Var, Value>,
+    executable: Vec<bool>,
+    edges: HashSet<(BlockId, BlockId)>,
+    edge_worklist: Vec<(BlockId, BlockId)>,
+    ssa_worklist: Vec<Site>,
+}
+
+impl<'a> Sccp<'a> {
+    fn new(cfg: &'a Cfg, args: &[FnHeaders]) -> Self {
+        let mut uses: HashMap<Var, Vec<Site>> = HashMap::new();
+        for id in cfg.ids() {
+            for (idx, instr) in cfg[id].contents.iter().enumerate() {
+                if let Instr::Value { args, .. } | Instr::Effect { args, .. } = instr {
+                    for arg in args.iter() {
+                        uses.entry(*arg).or_default().push((id, idx));
+                    }
+                }
+            }
+        }
+
+        Sccp {
+            cfg,
+            labels: cfg.label_map(),
+            uses,
+            values: args.iter().map(|x| (x.name, Value::Unknown)).collect(),
+            executable: vec![false; cfg.ids().count()],
+            edges: HashSet::new(),
+            edge_worklist: Vec::new(),
+            ssa_worklist: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) {
+        let entry = self.cfg.entry();
+        self.visit_block(entry);
+
+        loop {
+            if let Some((from, to)) = self.edge_worklist.pop() {
+                if !self.edges.insert((from, to)) {
+                    continue;
+                }
+                if self.executable[to.index()] {
+                    // Only the phis can see the new edge
+                    for (idx, instr) in self.cfg[to].contents.iter().enumerate() {
+                        if matches!(instr, Instr::Value { op: Op::Phi, .. }) {
+                            self.visit_instr((to, idx));
+                        }
+                    }
+                } else {
+                    self.visit_block(to);
+                }
+            } else if let Some(site) = self.ssa_worklist.pop() {
+                if self.executable[site.0.index()] {
+                    self.visit_instr(site);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn visit_block(&mut self, id: BlockId) {
+        self.executable[id.index()] = true;
+        for idx in 0..self.cfg[id].contents.len() {
+            self.visit_instr((id, idx));
+        }
+        // Blocks that don't end in a branch are only visited once, here
+        if !matches!(self.cfg[id].link(), Link::Branch { .. }) {
+            for target in self.cfg.successors(id) {
+                self.edge_worklist.push((id, target));
+            }
+        }
+    }
+
+    fn visit_instr(&mut self, (id, idx): Site) {
+        let cfg = self.cfg;
+        let value = match &cfg[id].contents.0[idx] {
+            Instr::Const { dest, r_type, value, .. } => Some((*dest, Some(Value::from_literal(value, r_type)))),
+            Instr::Value { op: Op::Phi, dest, args, labels, .. } => {
+                // Only arguments along edges known to be taken count
+                let value = args.iter().zip(labels.iter())
+                    .filter(|(_, label)| self.labels.get(label).is_some_and(|pred| self.edges.contains(&(*pred, id))))
+                    .filter_map(|(arg, _)| self.values.get(arg).copied())
+                    .reduce(|a, b| if a == b { a } else { Value::Unknown });
+                Some((*dest, value))
+            }
+            Instr::Value { op, dest, args, .. } if op.is_pure() => {
+                let values: Option<Vec<Value>> = args.iter().map(|x| self.values.get(x).copied()).collect();
+                let value = values.map(|values| {
+                    if values.contains(&Value::Unknown) {
+                        Value::Unknown
+                    } else {
+                        evaluate(*op, &values).unwrap_or(Value::Unknown)
+                    }
+                });
+                Some((*dest, value))
+            }
+            Instr::Value { dest, .. } => Some((*dest, Some(Value::Unknown))),
+            Instr::Effect { op: Op::Br, args, .. } => {
+                if let Link::Branch { true_branch, false_branch } = cfg[id].link() {
+                    match self.values.get(&args[0]) {
+                        Some(Value::Bool(true)) => self.edge_worklist.push((id, true_branch)),
+                        Some(Value::Bool(false)) => self.edge_worklist.push((id, false_branch)),
+                        Some(_) => {
+                            self.edge_worklist.push((id, true_branch));
+                            self.edge_worklist.push((id, false_branch));
+                        }
+                        None => {}
+                    }
+                }
+                None
+            }
+            _ => None,
+        };
+
+        // Values only ever move down from missing to a constant to unknown,
+        // so any change is news for the uses
+        if let Some((dest, Some(value))) = value {
+            if self.values.get(&dest) != Some(&value) {
+                self.values.insert(dest, value);
+                if let Some(uses) = self.uses.get(&dest) {
+                    self.ssa_worklist.extend(uses.iter().copied());
+                }
+            }
+        }
+    }
+}
+
+/// Sparse conditional constant propagation for functions in SSA form,
+/// following Wegman and Zadeck. Values are only propagated along edges found
+/// to be executable, starting from the entry, so a constant condition keeps
+/// the other side of its branch out of the phis it would otherwise reach.
+///
+/// Afterwards values that are known constants are computed with a `const`,
+/// branches only ever taken one way become jumps and blocks that are never
+/// executed are removed, along with the phi arguments coming from them. The
+/// result is still in SSA form. Functions not in SSA form are left alone.
+pub fn sparse_conditional_constant_propagation(cfg: &mut Cfg, args: &[FnHeaders]) {
+    if cfg.is_empty() || !is_ssa(cfg, args) {
+        return;
+    }
+
+    let (values, edges, executable) = {
+        let mut sccp = Sccp::new(cfg, args);
+        sccp.run();
+        (sccp.values, sccp.edges, sccp.executable)
+    };
+    let labels = cfg.label_map();
+
+    // Blocks that are never executed are left as they are, to be removed
+    let ids: Vec<BlockId> = cfg.ids().filter(|x| executable[x.index()]).collect();
+    for id in ids.iter().copied() {
+        for instr in cfg[id].contents.iter_mut() {
+            if let Instr::Value { op, dest, r_type, args, labels: arg_labels, .. } = instr {
+                if *op == Op::Phi {
+                    let mut idx = 0;
+                    while idx < args.len() {
+                        if labels.get(&arg_labels[idx]).is_some_and(|pred| edges.contains(&(*pred, id))) {
+                            idx += 1;
+                        } else {
+                            args.remove(idx);
+                            arg_labels.remove(idx);
+                        }
+                    }
+                }
+                // Infinities and NaN have no way of being written as a literal
+                let value = values.get(dest)
+                    .and_then(|x| x.to_literal())
+                    .filter(|x| !matches!(x, Literal::Float(f) if !f.is_finite()));
+                if let Some(value) = value {
+                    *instr = Instr::Const { op: Op::Const, dest: *dest, r_type: r_type.clone(), value };
+                }
+            }
+        }
+    }
+
+    for id in ids {
+        if let Link::Branch { true_branch, false_branch } = cfg[id].link() {
+            match (edges.contains(&(id, true_branch)), edges.contains(&(id, false_branch))) {
+                (true, false) => cfg.set_jump(id, true_branch),
+                (false, true) => cfg.set_jump(id, false_branch),
+                _ => {}
+            }
+        }
+    }
+    cfg.remove_unreachable();
+}
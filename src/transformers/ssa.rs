@@ -1,19 +1,17 @@
-use super::cfg::{Block, Node, Link};
+use super::cfg::{Block, BlockId, Cfg, Link};
 use super::dominance::DominanceTree;
-use crate::serde_structs::namer;
-use crate::serde_structs::structs::{Instr, Label, Op, Type, Var, FnHeaders};
+use crate::serde_structs::structs::{Instr, Interner, Label, Op, Type, Var, FnHeaders};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::ops::{Index, IndexMut};
-use std::rc::{Rc, Weak};
 
-fn identify_definitions(nodes: &[Rc<Node>], headers: &[FnHeaders]) -> HashMap<Var, (HashSet<Label>, Type)> {
-    let mut var_map: HashMap<Var, (HashSet<Label>, Type)> = HashMap::new();
+fn identify_definitions(cfg: &mut Cfg, headers: &[FnHeaders]) -> HashMap<Var, (HashSet<BlockId>, Type)> {
+    let mut var_map: HashMap<Var, (HashSet<BlockId>, Type)> = HashMap::new();
+    let entry = cfg.entry();
     {
-    let contents: &mut Block = &mut nodes[0].contents.borrow_mut();
+    let contents: &mut Block = &mut cfg[entry].contents;
 
     for header in headers {
-        let mut set = HashSet::<Label>::with_capacity(1);
-        set.insert(nodes[0].label());
+        let mut set = HashSet::<BlockId>::with_capacity(1);
+        set.insert(entry);
         var_map.insert(header.name, (set, header.r_type.clone()));
         contents.0.insert(1, Instr::Value {
             op: Op::Id,
@@ -27,18 +25,17 @@ fn identify_definitions(nodes: &[Rc<Node>], headers: &[FnHeaders]) -> HashMap<Va
     }
 }
 
-    for block in nodes {
-        let instrs: &Block = &block.contents.borrow();
-        for instr in instrs.0.iter() {
+    for id in cfg.ids() {
+        for instr in cfg[id].contents.iter() {
             match instr {
                 Instr::Const { dest, r_type, .. } | Instr::Value { dest, r_type, .. } => {
                     let contains: bool = var_map.contains_key(dest);
 
                     if contains {
-                        var_map.get_mut(dest).unwrap().0.insert(block.label());
+                        var_map.get_mut(dest).unwrap().0.insert(id);
                     } else {
-                        let mut set = HashSet::<Label>::with_capacity(1);
-                        set.insert(block.label());
+                        let mut set = HashSet::<BlockId>::with_capacity(1);
+                        set.insert(id);
                         var_map.insert(*dest, (set, r_type.clone()));
                     }
                 }
@@ -50,30 +47,30 @@ fn identify_definitions(nodes: &[Rc<Node>], headers: &[FnHeaders]) -> HashMap<Va
 }
 
 fn insert_phi_nodes(
-    nodes: &mut [Rc<Node>], headers: &[FnHeaders]
-) -> (DominanceTree, HashMap<Var, (HashSet<Label>, Type)>) {
+    cfg: &mut Cfg, headers: &[FnHeaders]
+) -> (DominanceTree, HashMap<Var, (HashSet<BlockId>, Type)>) {
 
-    let mut def_map = identify_definitions(nodes, headers);
-    let dom_tree = DominanceTree::new(nodes);
-    // eprintln!("dom tree computed");
+    let mut def_map = identify_definitions(cfg, headers);
+    let dom_tree = DominanceTree::new(cfg);
 
-    for (var, (defs, r_type)) in def_map.iter_mut() {
-        let mut queue: VecDeque<Label> = defs.iter().cloned().collect();
-        let def_len = queue.len();
+    // Visited in a fixed order so the phis come out the same on every run
+    let mut vars: Vec<Var> = def_map.keys().copied().collect();
+    vars.sort();
+
+    for var in vars.iter() {
+        let (defs, r_type) = def_map.get_mut(var).unwrap();
+        let mut sorted_defs: Vec<BlockId> = defs.iter().copied().collect();
+        sorted_defs.sort();
+        let mut queue: VecDeque<BlockId> = sorted_defs.into_iter().collect();
 
         if queue.len() != 1 {
-            // eprintln!(">>>> checking defs of {} total {}", var, def_len);
-            while let Some(block_label) = queue.pop_front() {
-                // eprintln!("queue length {}", queue.len());
-                // eprintln!("frontier for {} is {:?} ", block_label, dom_tree.compute_frontier(&block_label));
-                for block in dom_tree.compute_frontier(&block_label) {
-                    // eprintln!("frontier for {} contains {}", block_label, block);
-
-                    let node = dom_tree.lookup_node(&block);
-                    let contents: &mut Block = &mut node.contents.borrow_mut();
+            while let Some(def_block) = queue.pop_front() {
+                let def_label = cfg[def_block].label();
+                for block in dom_tree.frontier(def_block) {
+                    let contents: &mut Block = &mut cfg[block].contents;
                     if contents.len() != 1 {
                         let mut found = false;
-                        for instr in contents.0.iter_mut() {
+                        for instr in contents.iter_mut() {
                             if let Instr::Value {
                                 op: Op::Phi,
                                 dest,
@@ -84,7 +81,7 @@ fn insert_phi_nodes(
                             {
                                 if dest == var {
                                     args.push(*var);
-                                    labels.push(block_label);
+                                    labels.push(def_label);
                                     found = true;
                                     break
                                 }
@@ -102,19 +99,14 @@ fn insert_phi_nodes(
                         r_type: r_type.clone(),
                         args: vec![*var],
                         funcs: vec![],
-                        labels: vec![block_label],
+                        labels: vec![def_label],
                     };
-                    // eprintln!("Inserting phi node for {} in {}", var, node.label());
                     contents.0.insert(1, new);
-                    defs.insert(node.label());
-
+                    defs.insert(block);
 
-                    if !queue.contains(&node.label()) {
-                        queue.push_back(node.label());
+                    if !queue.contains(&block) {
+                        queue.push_back(block);
                     }
-
-
-
                 }
             }
         }
@@ -130,7 +122,7 @@ struct RenameStack {
 
 impl RenameStack {
     // TODO: Fix this definition
-    fn new(vars: std::collections::hash_map::Keys<Var, (HashSet<Label>, Type)>, headers: &[FnHeaders]) -> Self {
+    fn new(vars: std::collections::hash_map::Keys<Var, (HashSet<BlockId>, Type)>, headers: &[FnHeaders]) -> Self {
         let mut stack_map = HashMap::<Var, Vec<Var>>::with_capacity(vars.len());
         for var in vars {
             // eprintln!("[[[[[[[[[[[INSERTING {}", var);
@@ -169,30 +161,27 @@ impl RenameStack {
 
     fn push_var(&mut self, old_name: &Var, new_name: Var) {
         // eprintln!("Old name: {}", old_name);
-        self.var_stacks.get_mut(old_name).unwrap().push(new_name)
+        self.var_stacks.get_mut(old_name).unwrap().push(new_name);
+        *self.pop_list.last_mut().unwrap().entry(*old_name).or_insert(0) += 1;
     }
 
     fn get_top(&self, old_name: &Var) -> Option<Var> {
         // eprintln!("Old name: {}", old_name);
-        match self.var_stacks.get(old_name).unwrap().last() {
-            Some(v) => Some(*v),
-            None => {
-                // eprintln!("There's no rename for {}", old_name);
-                None},
-        }
+        self.var_stacks.get(old_name).unwrap().last().copied()
     }
 }
 
-fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack, headers: &[Var]) {
+fn rename(cfg: &mut Cfg, id: BlockId, dom_tree: &DominanceTree, stack: &mut RenameStack, headers: &[Var],
+          names: &mut Interner) {
     stack.increase_layer();
+    let block_label = cfg[id].label();
     {
-        let contents: &mut Block = &mut node.contents.borrow_mut();
-        let block = &mut contents.0;
+        let block = &mut cfg[id].contents.0;
         for instr in block.iter_mut() {
             match instr {
                 // Constants will only define a new name
                 Instr::Const { dest, .. } => {
-                    let new_name = Var(namer().fresh(&dest.0));
+                    let new_name = Var(names.fresh(*dest));
                     stack.push_var(dest, new_name);
                     *dest = new_name;
 
@@ -201,20 +190,20 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
                 Instr::Value {
                     op: Op::Phi, dest, ..
                 } => {
-                    let new_name = Var(namer().fresh(&dest.0));
+                    let new_name = Var(names.fresh(*dest));
                     stack.push_var(dest, new_name);
                     *dest = new_name;
                 }
                 // Otherwise, update args then the name
-                Instr::Value { op, dest, args, .. } => {
+                Instr::Value { dest, args, .. } => {
                     for arg in args.iter_mut() {
                         *arg = match stack.get_top(arg) {
                             Some(var) => var,
                             None if headers.contains(arg) => *arg,
-                            _ => panic!("Unknown variable {}", arg),
+                            _ => panic!("Unknown variable {}", names.resolve(*arg)),
                         }
                     }
-                    let new_name = Var(namer().fresh(&dest.0));
+                    let new_name = Var(names.fresh(*dest));
                     stack.push_var(dest, new_name);
                     *dest = new_name;
                 }
@@ -224,7 +213,7 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
                         *arg = match stack.get_top(arg) {
                             Some(var) => var,
                             None if headers.contains(arg) => *arg,
-                            _ => panic!("Unknown variable {}", arg),
+                            _ => panic!("Unknown variable {}", names.resolve(*arg)),
                         }
                     }
                 }
@@ -233,10 +222,8 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
         }
     }
 
-    for successor in node.successor_refs() {
-        // eprintln!("updating successors");
-        let contents: &mut Block = &mut successor.contents.borrow_mut();
-        let block = &mut contents.0;
+    for successor in cfg.successors(id) {
+        let block = &mut cfg[successor].contents.0;
         for instr in block.iter_mut() {
             if let Instr::Value { op: op @ Op::Phi, args, dest, labels, ..} = instr {
                 let mut index:usize = 0;
@@ -251,7 +238,7 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
                 if !found {
                     // eprintln!("adding arg to phi node for {}", dest);
                     args.push(stack.get_top(dest).unwrap());
-                    labels.push(node.label())
+                    labels.push(block_label)
                     // panic!("No arg to rename? {:?} {} {}", args, dest, successor.label())
                 } else {
                     // eprintln!("rewriting phi node");
@@ -261,7 +248,7 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
                     if let Some(renamed) = stack.get_top(var){
                         *var = renamed;
                         let label = labels.get_mut(index).unwrap();
-                        *label = node.label();
+                        *label = block_label;
                     } else {
                         // The phi node is not valid along this path. Remove
                         *op = Op::Nop;
@@ -277,12 +264,11 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
         }
     }
 
-    for mut child in dom_tree.get_children(&node.label()) {
-        rename(&mut child, dom_tree, stack, headers);
+    for child in dom_tree.children(id) {
+        rename(cfg, child, dom_tree, stack, headers, names);
     }
 
-    let contents: &mut Block = &mut node.contents.borrow_mut();
-    let block = &mut contents.0;
+    let block = &mut cfg[id].contents.0;
     // TODO: Figure out how to get rid of this
     for instr in block.iter_mut() {
         if let Instr::Value {op: op @ Op::Phi, args, labels, ..} = instr{
@@ -297,104 +283,81 @@ fn rename(node: &mut Rc<Node>, dom_tree: &DominanceTree, stack: &mut RenameStack
         }
     }
 
-    block.retain(|x| if let Instr::Value { op:Op::Nop, ..} = x {
-        false
-    } else{
-        true
-    });
+    block.retain(|x| !matches!(x, Instr::Value { op:Op::Nop, ..}));
 
     stack.decrease_layer();
 
 }
 
-pub fn to_ssa(nodes: &mut Vec<Rc<Node>>, headers: &[FnHeaders]) {
-    for node in nodes.iter() {
-        node.normalize()
+/// Every variable is defined once, counting the function arguments. This is
+/// the case for anything coming out of `to_ssa`.
+pub fn is_ssa(cfg: &Cfg, args: &[FnHeaders]) -> bool {
+    let mut defined: HashSet<Var> = args.iter().map(|x| x.name).collect();
+    cfg.ids().all(|id| {
+        cfg[id].contents.iter().filter_map(Instr::dest).all(|dest| defined.insert(dest))
+    })
+}
+
+pub fn to_ssa(cfg: &mut Cfg, headers: &[FnHeaders], names: &mut Interner) {
+    if cfg.is_empty() {
+        return;
     }
-    let (dom_tree, mut def_map) = insert_phi_nodes(&mut nodes[..], headers);
+    cfg.normalize();
+    let (dom_tree, def_map) = insert_phi_nodes(cfg, headers);
 
     let mut stack = RenameStack::new(def_map.keys(), headers);
     let header_vars: Vec<Var> = headers.iter().map(|x|x.name).collect();
 
-    rename(&mut nodes[0], &dom_tree, &mut stack, &header_vars[..])
+    rename(cfg, dom_tree.root(), &dom_tree, &mut stack, &header_vars[..], names)
 }
 
-pub fn from_ssa(nodes: &mut Vec<Rc<Node>>) {
-    let mut label_map: HashMap<Label, Rc<Node>> = HashMap::new();
+/// Replaces each phi with copies placed on the incoming edges. Every edge
+/// into a block with phis gets a new block holding its copies.
+pub fn from_ssa(cfg: &mut Cfg, names: &mut Interner) {
+    let label_map = cfg.label_map();
+    let mut edges: Vec<((BlockId, Label), Vec<Instr>)> = Vec::new();
 
-    for node in nodes.iter() {
-        node.clear_predecessors();
-        label_map.insert(node.label(), node.clone());
-    }
-
-    let mut new_nodes: Vec<Rc<Node>> = Vec::new();
-    let mut fix_list: Vec<(Label, Rc<Node>)> = Vec::new();
-
-    let mut new_nodes_map: HashMap<(Label, Label), Rc<Node>> = HashMap::new();
-
-    for node in nodes.iter_mut() {
-        let block = &mut *node.contents.borrow_mut();
-        // let mut correction_list: Vec<> = vec! [];
-
-        for instr in block.0.iter_mut() {
+    for id in cfg.ids() {
+        for instr in cfg[id].contents.iter() {
             if let Instr::Value {op: Op::Phi, args, labels, dest, r_type,..} = instr {
                 for (var, label) in args.iter().zip(labels.iter()) {
-
-                    let new_node = new_nodes_map.entry((node.label(), *label)).or_insert(Node::empty_block());
-                    new_node.insert_id(*var, *dest, r_type.clone());
-                    // if *label != node.label() {
-                    //     label_map.get_mut(label).unwrap().replace_link(node.label(), Rc::downgrade(&new_node), new_node.label());
-                    //     new_nodes.push(new_node);
-                    // } else {
-                    //     fix_list.push((*label, new_node));
-                    // }
+                    let copy = Instr::Value {
+                        op: Op::Id,
+                        dest: *dest,
+                        r_type: r_type.clone(),
+                        args: vec![*var],
+                        funcs: Vec::new(),
+                        labels: Vec::new(),
+                    };
+                    match edges.iter_mut().find(|(edge, _)| *edge == (id, *label)) {
+                        Some((_, copies)) => copies.push(copy),
+                        None => edges.push(((id, *label), vec![copy])),
+                    }
                 }
             }
         }
     }
 
-    for ((to, from), new_node) in new_nodes_map.drain() {
-        {
-            let target = &label_map[&to];
-            new_node.add_jump(Rc::downgrade(target), target.label());
-        }
-        label_map[&from].replace_link(to,
-                            Rc::downgrade(&new_node),
-                                    new_node.label());
-        nodes.push(new_node);
+    for ((to, from_label), mut copies) in edges {
+        // phi arguments for edges that no longer exist are dropped
+        let from = match label_map.get(&from_label) {
+            Some(from) if cfg.successors(*from).contains(&to) => *from,
+            _ => continue,
+        };
+        let to_label = cfg[to].label();
+        copies.push(Instr::Effect {
+            op: Op::Jmp,
+            args: Vec::new(),
+            funcs: Vec::new(),
+            labels: vec![to_label],
+        });
+        let new = cfg.add_block(names.fresh_label(), copies, Link::Jump(to));
+        cfg.redirect(from, to, new);
     }
 
-    // for (label, new_node) in fix_list {
-    //     // eprintln!("{} {}", label, new_node.label());
-    //     label_map.get_mut(&label).unwrap().replace_link(label, Rc::downgrade(&new_node), new_node.label());
-    //     new_nodes.push(new_node);
-    // }
-
-    nodes.append(&mut new_nodes);
-
-    let len = nodes.len();
-
-    for (idx,node) in nodes.iter_mut().enumerate() {
-        node.contents.borrow_mut().0.retain(|x| {
-            if let Instr::Value {op:Op::Phi, ..} = x {
-                false
-            } else {
-                true
-            }
+    for node in cfg.nodes_mut() {
+        node.contents.0.retain(|x| {
+            !matches!(x, Instr::Value {op:Op::Phi, ..})
         });
-
-        if let Some(Link::Exit) = &*node.out.borrow() {
-            if idx != len-1{
-                node.contents.borrow_mut().0.push(Instr::Effect {
-                    op: Op::Ret,
-                    args: Vec::new(),
-                    funcs: Vec::new(),
-                    labels: Vec::new(),
-                });
-            }
-
-        }
-
     }
-
 }
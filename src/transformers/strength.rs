@@ -0,0 +1,212 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::dominance::DominanceTree;
+use super::loop_simplify::insert_preheader;
+use crate::analysis::{self, Loop, LoopForest};
+use crate::analysis::induction::{constant_int, induction_variables, initial_value, BasicInduction, Inductions, Site, Stride};
+use crate::serde_structs::structs::{FnHeaders, Instr, Interner, Literal, Op, Type, Var};
+
+fn value(op: Op, dest: Var, r_type: Type, args: Vec<Var>) -> Instr {
+    Instr::Value {
+        op,
+        dest,
+        r_type,
+        args,
+        funcs: Vec::new(),
+        labels: Vec::new(),
+    }
+}
+
+fn instr_at(cfg: &Cfg, site: Site) -> &Instr {
+    &cfg[site.0].contents.0[site.1]
+}
+
+// The operation, type and arguments of the value computed at `site`
+fn computation(cfg: &Cfg, site: Site) -> (Op, Type, Vec<Var>) {
+    match instr_at(cfg, site) {
+        Instr::Value { op, r_type, args, .. } => (*op, r_type.clone(), args.clone()),
+        _ => unreachable!("Induction variables are defined by value operations"),
+    }
+}
+
+fn defined_in(cfg: &Cfg, natural_loop: &Loop, var: Var) -> bool {
+    natural_loop.body.iter().any(|id| cfg[*id].contents.iter().any(|x| x.dest() == Some(var)))
+}
+
+// The bound to test a basic variable multiplied by `scale` against in place of
+// the test `basic <op> bound`, which holds for as long as the loop carries
+// on. This is only the same test when none of the products overflow, so it
+// needs the start, step, bound and scale to all be constants, with the step
+// moving the variable towards the bound, and every value the variable can be
+// tested with times the scale to fit in an int.
+fn scaled_test_bound(cfg: &Cfg, natural_loop: &Loop, fn_args: &[FnHeaders], basic: &BasicInduction, op: Op, bound: Var, scale: Var) -> Option<i64> {
+    let scale = constant_int(cfg, scale).filter(|x| *x > 0)?;
+    let bound = constant_int(cfg, bound)?;
+    let start = initial_value(cfg, natural_loop, fn_args, basic.var)?;
+    let step = constant_int(cfg, basic.step)?;
+    let step = if basic.decreasing { step.checked_neg()? } else { step };
+    if step == 0 || matches!(op, Op::Lt | Op::Le) != (step > 0) {
+        return None;
+    }
+
+    // The variable stops within a step of the bound, or goes no further
+    // than one step from where it started if the test fails straight away
+    let lowest = start.min(bound).checked_sub(step.checked_abs()?)?;
+    let highest = start.max(bound).checked_add(step.checked_abs()?)?;
+    lowest.checked_mul(scale)?;
+    highest.checked_mul(scale)?;
+    bound.checked_mul(scale)
+}
+
+/// Replaces each induction variable computed with a multiplication by a new
+/// variable that is set up in the preheader and then moves by a fixed amount
+/// whenever its basic variable does.
+///
+/// Exit tests comparing a basic variable against a constant bound are then
+/// rewritten against one of the new variables, when that is sure to give the
+/// same result. A basic variable left with no uses in the loop or after it is
+/// removed.
+fn reduce_loop(cfg: &mut Cfg, natural_loop: &Loop, inductions: &Inductions, fn_args: &[FnHeaders], names: &mut Interner) {
+    let mut preheader: Vec<Instr> = Vec::new();
+    let mut updates: Vec<(Site, Instr)> = Vec::new();
+    let mut replacements: Vec<(Site, Instr)> = Vec::new();
+    // Reduced variables that are exactly a basic variable times a constant
+    let mut scaled: Vec<(Var, Var, Var)> = Vec::new();
+
+    for derived in inductions.derived.iter() {
+        let scale = match derived.stride {
+            Stride::Scaled(scale) => scale,
+            Stride::One => continue,
+        };
+        let basic = inductions.basic_var(derived.basic).unwrap();
+        let (op, r_type, mut args) = computation(cfg, derived.site);
+        let reduced = Var(names.fresh(derived.var));
+
+        if let Some(from) = derived.from {
+            let from_site = inductions.derived_var(from).unwrap().site;
+            let (from_op, from_type, from_args) = computation(cfg, from_site);
+            let initial = Var(names.fresh(from));
+            preheader.push(value(from_op, initial, from_type, from_args));
+            for arg in args.iter_mut().filter(|x| **x == from) {
+                *arg = initial;
+            }
+        } else if op == Op::Mul {
+            scaled.push((basic.var, scale, reduced));
+        }
+        preheader.push(value(op, reduced, r_type.clone(), args));
+
+        let mut amount = Var(names.fresh(derived.var));
+        preheader.push(value(Op::Mul, amount, Type::Int, vec![scale, basic.step]));
+        let update_op = match (derived.pointer, basic.decreasing) {
+            (true, true) => {
+                let zero = names.intern("zero");
+                let zero = Var(names.fresh(zero));
+                let negated = Var(names.fresh(derived.var));
+                preheader.push(Instr::Const { op: Op::Const, dest: zero, r_type: Type::Int, value: Literal::Int(0) });
+                preheader.push(value(Op::Sub, negated, Type::Int, vec![zero, amount]));
+                amount = negated;
+                Op::PtrAdd
+            }
+            (true, false) => Op::PtrAdd,
+            (false, true) => Op::Sub,
+            (false, false) => Op::Add,
+        };
+        updates.push((basic.site, value(update_op, reduced, r_type.clone(), vec![reduced, amount])));
+        replacements.push((derived.site, value(Op::Id, derived.var, r_type, vec![reduced])));
+    }
+
+    if preheader.is_empty() {
+        return;
+    }
+    for ((id, idx), replacement) in replacements {
+        cfg[id].contents.0[idx] = replacement;
+    }
+
+    // Linear function test replacement on the exit tests
+    for exiting in natural_loop.exiting.iter().copied() {
+        let block = &cfg[exiting].contents.0;
+        let (cond, continue_on_true) = match (block.last(), cfg[exiting].link()) {
+            (Some(Instr::Effect { op: Op::Br, args, .. }), Link::Branch { true_branch, .. }) => {
+                (args[0], natural_loop.contains(true_branch))
+            }
+            _ => continue,
+        };
+        let idx = match (0..block.len()).rev().find(|x| block[*x].dest() == Some(cond)) {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let (op, _, args) = computation(cfg, (exiting, idx));
+        if !matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge) || args.len() != 2 {
+            continue;
+        }
+        let basic_pos = match args.iter().position(|x| inductions.basic_var(*x).is_some()) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let bound = args[1 - basic_pos];
+        if defined_in(cfg, natural_loop, bound) {
+            continue;
+        }
+        // The test the loop carries on with, with the variable on the left
+        let op = if basic_pos == 0 { op } else { op.swapped().unwrap() };
+        let op = if continue_on_true { op } else { op.negated().unwrap() };
+
+        let basic = inductions.basic_var(args[basic_pos]).unwrap();
+        let replacement = scaled.iter().filter(|(var, _, _)| *var == basic.var).find_map(|(_, scale, reduced)| {
+            let scaled_bound = scaled_test_bound(cfg, natural_loop, fn_args, basic, op, bound, *scale)?;
+            Some((scaled_bound, *reduced))
+        });
+        if let Some((value, reduced)) = replacement {
+            let scaled_bound = Var(names.fresh(bound));
+            preheader.push(Instr::Const { op: Op::Const, dest: scaled_bound, r_type: Type::Int, value: Literal::Int(value) });
+            if let Instr::Value { args, .. } = &mut cfg[exiting].contents.0[idx] {
+                args[basic_pos] = reduced;
+                args[1 - basic_pos] = scaled_bound;
+            }
+        }
+    }
+
+    let live = analysis::live_variables(cfg);
+    let unused: Vec<(Var, BlockId)> = inductions.basic.iter().filter(|basic| {
+        let used_inside = natural_loop.body.iter().any(|id| {
+            cfg[*id].contents.iter().enumerate().any(|(idx, instr)| {
+                (*id, idx) != basic.site && match instr {
+                    Instr::Value { args, .. } | Instr::Effect { args, .. } => args.contains(&basic.var),
+                    _ => false,
+                }
+            })
+        });
+        let used_after = natural_loop.exits.iter().any(|x| live[x.index()].in_data.contains(&basic.var));
+        !used_inside && !used_after
+    }).map(|basic| (basic.var, basic.site.0)).collect();
+
+    // Later positions go first so the earlier sites stay valid
+    updates.sort_by_key(|(site, _)| std::cmp::Reverse(*site));
+    for ((id, idx), update) in updates {
+        cfg[id].contents.0.insert(idx + 1, update);
+    }
+    for (var, id) in unused {
+        cfg[id].contents.0.retain(|x| x.dest() != Some(var));
+    }
+
+    insert_preheader(cfg, natural_loop, preheader, names);
+}
+
+/// Strength reduction over every loop, innermost first. Only programs not in
+/// SSA form are affected, since basic induction variables are recognized by
+/// their updates rather than through phis.
+pub fn strength_reduce(cfg: &mut Cfg, args: &[FnHeaders], names: &mut Interner) {
+    if cfg.is_empty() {
+        return;
+    }
+
+    let headers = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+    for header in headers {
+        if header == cfg.entry() {
+            continue;
+        }
+        let forest = LoopForest::new(cfg, &DominanceTree::new(cfg));
+        let natural_loop = forest.loops().iter().find(|x| x.header == header).unwrap();
+        let inductions = induction_variables(cfg, natural_loop);
+        reduce_loop(cfg, natural_loop, &inductions, args, names);
+    }
+}
@@ -0,0 +1,306 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::dominance::DominanceTree;
+use super::loop_simplify::outside_predecessors;
+use crate::analysis::{Loop, LoopForest};
+use crate::analysis::induction::{constant_int, induction_variables, initial_value};
+use crate::serde_structs::structs::{FnHeaders, Instr, Interner, Literal, Op, Type, Var};
+use std::collections::HashMap;
+
+/// Loops are only unrolled completely when they run at most this many times
+const MAX_FULL_UNROLL: i64 = 16;
+
+/// A loop that only exits from its header, through a test comparing a basic
+/// induction variable with a loop-invariant bound. The loop carries on while
+/// `var <op> bound` holds, so `op` here already accounts for which way the
+/// branch goes and which side of the comparison the variable was on.
+struct CountedLoop {
+    header: BlockId,
+    latch: BlockId,
+    // The successors of the header inside and outside the loop
+    body: BlockId,
+    exit: BlockId,
+    var: Var,
+    step: i64,
+    bound: Var,
+    op: Op,
+    // Whether the variable is updated in the header before the test reads it
+    updated_first: bool,
+}
+
+fn compare(op: Op, a: i64, b: i64) -> bool {
+    match op {
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        _ => a >= b,
+    }
+}
+
+fn counted_loop(cfg: &Cfg, natural_loop: &Loop, dom_tree: &DominanceTree) -> Option<CountedLoop> {
+    let header = natural_loop.header;
+    if natural_loop.latches.len() != 1 || natural_loop.exiting != [header] {
+        return None;
+    }
+    let latch = natural_loop.latches[0];
+
+    let block = &cfg[header].contents.0;
+    let (cond, true_branch, false_branch) = match (block.last(), cfg[header].link()) {
+        (Some(Instr::Effect { op: Op::Br, args, .. }), Link::Branch { true_branch, false_branch }) => {
+            (args[0], true_branch, false_branch)
+        }
+        _ => return None,
+    };
+    let continue_on_true = natural_loop.contains(true_branch);
+    let (body, exit) = if continue_on_true { (true_branch, false_branch) } else { (false_branch, true_branch) };
+
+    let test = (0..block.len()).rev().find(|x| block[*x].dest() == Some(cond))?;
+    let (op, args) = match &block[test] {
+        Instr::Value { op: op @ (Op::Lt | Op::Le | Op::Gt | Op::Ge), args, .. } => (*op, args),
+        _ => return None,
+    };
+
+    let inductions = induction_variables(cfg, natural_loop);
+    let defined_in_loop = |var: Var| {
+        natural_loop.body.iter().any(|id| cfg[*id].contents.iter().any(|x| x.dest() == Some(var)))
+    };
+    // The test may read the variable through a copy made earlier in the header
+    let resolve = |arg: Var| -> Option<(Var, usize)> {
+        if inductions.basic_var(arg).is_some() {
+            return Some((arg, test));
+        }
+        let copy = (0..test).rev().find(|x| block[*x].dest() == Some(arg))?;
+        match &block[copy] {
+            Instr::Value { op: Op::Id, args, .. } if inductions.basic_var(args[0]).is_some() => {
+                let update = inductions.basic_var(args[0]).unwrap().site;
+                let between = update.0 == header && copy < update.1 && update.1 < test;
+                if between { None } else { Some((args[0], copy)) }
+            }
+            _ => None,
+        }
+    };
+
+    let (var, read, bound, op) = match (resolve(args[0]), resolve(args[1])) {
+        (Some((var, read)), None) => (var, read, args[1], op),
+        (None, Some((var, read))) => (var, read, args[0], op.swapped().unwrap()),
+        _ => return None,
+    };
+    if defined_in_loop(bound) {
+        return None;
+    }
+    let op = if continue_on_true { op } else { op.negated().unwrap() };
+
+    let basic = inductions.basic_var(var).unwrap();
+    let step = constant_int(cfg, basic.step)?;
+    let step = if basic.decreasing { step.wrapping_neg() } else { step };
+    if !dom_tree.dominates(basic.site.0, latch) {
+        return None;
+    }
+    let updated_first = basic.site.0 == header && basic.site.1 < read;
+
+    Some(CountedLoop { header, latch, body, exit, var, step, bound, op, updated_first })
+}
+
+// How many times the body runs, when the bounds are constants and that is
+// no more than `MAX_FULL_UNROLL`
+fn trip_count(cfg: &Cfg, natural_loop: &Loop, args: &[FnHeaders], counted: &CountedLoop) -> Option<i64> {
+    let mut current = initial_value(cfg, natural_loop, args, counted.var)?;
+    let bound = constant_int(cfg, counted.bound)?;
+    if counted.updated_first {
+        current = current.wrapping_add(counted.step);
+    }
+    for trips in 0..=MAX_FULL_UNROLL {
+        if !compare(counted.op, current, bound) {
+            return Some(trips);
+        }
+        current = current.wrapping_add(counted.step);
+    }
+    None
+}
+
+/// Replaces the loop with `trips` copies of its iterations laid end to end,
+/// each going straight from the header into the body, followed by one last
+/// copy of the header going to the exit.
+fn unroll_fully(cfg: &mut Cfg, natural_loop: &Loop, counted: &CountedLoop, trips: i64, names: &mut Interner) {
+    let outside = outside_predecessors(cfg, natural_loop);
+    let copies: Vec<HashMap<BlockId, BlockId>> = (0..=trips)
+        .map(|_| cfg.duplicate(&natural_loop.body, names))
+        .collect();
+
+    for pred in outside {
+        cfg.redirect(pred, counted.header, copies[0][&counted.header]);
+    }
+    for (idx, copy) in copies.iter().enumerate() {
+        if idx as i64 == trips {
+            cfg.set_jump(copy[&counted.header], counted.exit);
+        } else {
+            cfg.set_jump(copy[&counted.header], copy[&counted.body]);
+            cfg.redirect(copy[&counted.latch], copy[&counted.header], copies[idx + 1][&counted.header]);
+        }
+    }
+}
+
+fn value(op: Op, dest: Var, r_type: Type, args: Vec<Var>) -> Instr {
+    Instr::Value {
+        op,
+        dest,
+        r_type,
+        args,
+        funcs: Vec::new(),
+        labels: Vec::new(),
+    }
+}
+
+fn int_const(dest: Var, val: i64) -> Instr {
+    Instr::Const { op: Op::Const, dest, r_type: Type::Int, value: Literal::Int(val) }
+}
+
+/// Runs `factor` iterations at a time without their exit tests, for as long
+/// as a guard in front finds that all of them would pass. The original loop
+/// is left behind to run whatever is left over.
+///
+/// The guard must not let a group take the variable past the end of its
+/// range. Against a constant bound it compares the variable with the bound
+/// moved back by the distance one group covers, worked out here. Otherwise
+/// it checks that the variable is far enough from the end of the range to
+/// move that far before comparing the moved variable with the bound.
+fn unroll_by(cfg: &mut Cfg, natural_loop: &Loop, counted: &CountedLoop, factor: usize, names: &mut Interner) {
+    let increasing = matches!(counted.op, Op::Lt | Op::Le);
+    if counted.step == 0 || increasing != (counted.step > 0) {
+        return;
+    }
+
+    // The last test of the group sees the variable moved on by this much
+    let moves = if counted.updated_first { factor } else { factor - 1 } as i64;
+    let offset = match counted.step.checked_mul(moves) {
+        Some(offset) => offset,
+        None => return,
+    };
+    // The furthest the variable can be from the bound with the whole group
+    // still in range
+    let end = if increasing { i64::MAX } else { i64::MIN };
+    let (limit, checked) = match constant_int(cfg, counted.bound) {
+        Some(bound) => (bound.checked_sub(offset), false),
+        None => (end.checked_sub(offset), true),
+    };
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return,
+    };
+
+    let outside = outside_predecessors(cfg, natural_loop);
+    let copies: Vec<HashMap<BlockId, BlockId>> = (0..factor)
+        .map(|_| cfg.duplicate(&natural_loop.body, names))
+        .collect();
+
+    let first_copy = copies[0][&counted.header];
+    cfg[first_copy].normalize();
+    cfg[counted.header].normalize();
+
+    let guard_limit = Var(names.fresh(counted.var));
+    let guard_cond = Var(names.fresh(counted.var));
+    let mut guard_instrs = vec![int_const(guard_limit, limit)];
+    if checked {
+        let in_range = Var(names.fresh(counted.var));
+        let guard_offset = Var(names.fresh(counted.var));
+        let last = Var(names.fresh(counted.var));
+        let passes = Var(names.fresh(counted.var));
+        let range_op = if increasing { Op::Le } else { Op::Ge };
+        // The moved variable is only meaningful when it is in range, but
+        // working it out regardless does no harm
+        guard_instrs.extend([
+            value(range_op, in_range, Type::Bool, vec![counted.var, guard_limit]),
+            int_const(guard_offset, offset),
+            value(Op::Add, last, Type::Int, vec![counted.var, guard_offset]),
+            value(counted.op, passes, Type::Bool, vec![last, counted.bound]),
+            value(Op::And, guard_cond, Type::Bool, vec![in_range, passes]),
+        ]);
+    } else {
+        guard_instrs.push(value(counted.op, guard_cond, Type::Bool, vec![counted.var, guard_limit]));
+    }
+    guard_instrs.push(Instr::Effect {
+        op: Op::Br,
+        args: vec![guard_cond],
+        funcs: Vec::new(),
+        labels: vec![cfg[first_copy].label(), cfg[counted.header].label()],
+    });
+    let guard = cfg.add_block(names.fresh_label(), guard_instrs, Link::Branch {
+        true_branch: first_copy,
+        false_branch: counted.header,
+    });
+
+    for pred in outside {
+        cfg.redirect(pred, counted.header, guard);
+    }
+    for (idx, copy) in copies.iter().enumerate() {
+        cfg.set_jump(copy[&counted.header], copy[&counted.body]);
+        let next = copies.get(idx + 1).map_or(guard, |x| x[&counted.header]);
+        cfg.redirect(copy[&counted.latch], copy[&counted.header], next);
+    }
+}
+
+/// Runs the first iteration of the loop as a copy in front of it, with the
+/// copy's back edges going into the original loop.
+fn peel(cfg: &mut Cfg, natural_loop: &Loop, names: &mut Interner) {
+    let outside = outside_predecessors(cfg, natural_loop);
+    let copy = cfg.duplicate(&natural_loop.body, names);
+    let header = natural_loop.header;
+
+    for pred in outside {
+        cfg.redirect(pred, header, copy[&header]);
+    }
+    for latch in natural_loop.latches.iter() {
+        cfg.redirect(copy[latch], copy[&header], header);
+    }
+}
+
+// Applies `f` to each innermost loop not headed by the entry block, with the
+// dominator tree and loop forest brought up to date before every call.
+// Functions in SSA form are left alone, since copying blocks would leave
+// phis pointing at the wrong predecessors.
+fn for_innermost_loops<F>(cfg: &mut Cfg, mut f: F)
+    where F: FnMut(&mut Cfg, &Loop, &DominanceTree) {
+    if cfg.is_empty() || cfg.has_phis() {
+        return;
+    }
+
+    let forest = LoopForest::new(cfg, &DominanceTree::new(cfg));
+    let headers: Vec<BlockId> = forest.loops().iter()
+        .filter(|x| x.children.is_empty() && x.header != cfg.entry())
+        .map(|x| x.header)
+        .collect();
+
+    // New blocks are only ever appended, so the headers stay valid until the
+    // unreachable blocks are cleared away at the end
+    for header in headers {
+        let dom_tree = DominanceTree::new(cfg);
+        let forest = LoopForest::new(cfg, &dom_tree);
+        if let Some(natural_loop) = forest.loops().iter().find(|x| x.header == header) {
+            f(cfg, natural_loop, &dom_tree);
+        }
+    }
+    cfg.remove_unreachable();
+}
+
+/// Unrolls innermost loops. Counted loops with constant bounds that run only
+/// a few times are unrolled completely and other counted loops are unrolled
+/// `factor` times with a remainder loop.
+pub fn unroll_loops(cfg: &mut Cfg, args: &[FnHeaders], factor: usize, names: &mut Interner) {
+    for_innermost_loops(cfg, |cfg, natural_loop, dom_tree| {
+        let counted = match counted_loop(cfg, natural_loop, dom_tree) {
+            Some(counted) => counted,
+            None => return,
+        };
+        match trip_count(cfg, natural_loop, args, &counted) {
+            Some(trips) => unroll_fully(cfg, natural_loop, &counted, trips, names),
+            None if factor > 1 => unroll_by(cfg, natural_loop, &counted, factor, names),
+            None => {}
+        }
+    })
+}
+
+/// Peels the first `count` iterations off every innermost loop
+pub fn peel_loops(cfg: &mut Cfg, count: usize, names: &mut Interner) {
+    for _ in 0..count {
+        for_innermost_loops(cfg, |cfg, natural_loop, _| peel(cfg, natural_loop, names))
+    }
+}
@@ -0,0 +1,92 @@
+use super::cfg::{BlockId, Cfg, Link};
+use super::dominance::DominanceTree;
+use super::loop_simplify::insert_preheader;
+use crate::analysis::{Loop, LoopForest};
+use crate::serde_structs::structs::{FnHeaders, Instr, Interner, Op, Var};
+
+/// A block in the loop ending with a branch on a condition the loop never
+/// changes. The condition has to be set on every path into the loop, by an
+/// argument or a definition dominating the header, so that it can be tested
+/// before the loop starts.
+fn invariant_branch(cfg: &Cfg, natural_loop: &Loop, dom_tree: &DominanceTree, args: &[FnHeaders]) -> Option<(BlockId, Var)> {
+    let defined_in = |id: BlockId, var: Var| cfg[id].contents.iter().any(|x| x.dest() == Some(var));
+
+    natural_loop.body.iter().copied().find_map(|id| {
+        let cond = match (cfg[id].contents.last(), cfg[id].link()) {
+            (Some(Instr::Effect { op: Op::Br, args, .. }), Link::Branch { true_branch, false_branch })
+                if true_branch != false_branch => args[0],
+            _ => return None,
+        };
+        if natural_loop.body.iter().any(|x| defined_in(*x, cond)) {
+            return None;
+        }
+        let set_before = args.iter().any(|x| x.name == cond)
+            || cfg.ids().any(|x| defined_in(x, cond) && dom_tree.dominates(x, natural_loop.header));
+        if set_before { Some((id, cond)) } else { None }
+    })
+}
+
+/// Makes a copy of the loop and picks between the two in the preheader by
+/// testing `cond`. The branch in `id` always goes the true way in the
+/// original and the false way in the copy. Returns the header of the copy.
+fn unswitch(cfg: &mut Cfg, natural_loop: &Loop, id: BlockId, cond: Var, names: &mut Interner) -> BlockId {
+    let (true_branch, false_branch) = match cfg[id].link() {
+        Link::Branch { true_branch, false_branch } => (true_branch, false_branch),
+        _ => unreachable!("Unswitching a block that doesn't branch"),
+    };
+
+    let preheader = insert_preheader(cfg, natural_loop, Vec::new(), names);
+    let copies = cfg.duplicate(&natural_loop.body, names);
+    let copy_header = copies[&natural_loop.header];
+
+    cfg.set_jump(id, true_branch);
+    cfg.set_jump(copies[&id], copies.get(&false_branch).copied().unwrap_or(false_branch));
+    cfg.set_branch(preheader, cond, natural_loop.header, copy_header);
+    copy_header
+}
+
+/// Unswitches loops on branches whose condition the loop doesn't change,
+/// innermost loops first. Each loop is copied so the copies can take the
+/// two sides of the branch without testing it, and both copies are then
+/// unswitched again on whatever invariant branches they have left. `budget`
+/// is the most instructions the copies may add to the function in total.
+///
+/// Functions with phis are left alone, as are loops headed by the entry
+/// block, since those can't be given a preheader to do the test in.
+pub fn unswitch_loops(cfg: &mut Cfg, args: &[FnHeaders], mut budget: usize, names: &mut Interner) {
+    if cfg.is_empty() || cfg.has_phis() {
+        return;
+    }
+
+    let mut worklist = LoopForest::new(cfg, &DominanceTree::new(cfg)).innermost_first();
+    worklist.reverse();
+
+    // Block ids stay valid until the blocks the branches no longer reach are
+    // removed at the end, since new blocks are only ever appended
+    while let Some(header) = worklist.pop() {
+        if header == cfg.entry() {
+            continue;
+        }
+        let dom_tree = DominanceTree::new(cfg);
+        let forest = LoopForest::new(cfg, &dom_tree);
+        let natural_loop = match forest.loops().iter().find(|x| x.header == header) {
+            Some(natural_loop) => natural_loop,
+            None => continue,
+        };
+        let (id, cond) = match invariant_branch(cfg, natural_loop, &dom_tree, args) {
+            Some(branch) => branch,
+            None => continue,
+        };
+
+        let size: usize = natural_loop.body.iter().map(|x| cfg[*x].contents.len()).sum();
+        if size > budget {
+            continue;
+        }
+        budget -= size;
+
+        let copy_header = unswitch(cfg, natural_loop, id, cond, names);
+        worklist.push(copy_header);
+        worklist.push(header);
+    }
+    cfg.remove_unreachable();
+}
@@ -1,4 +1,4 @@
-TURNT_TESTS=("global_dce" "local_dce" "lvn" "orphan" "df")
+TURNT_TESTS=("global_dce" "local_dce" "lvn" "orphan" "df" "text_format" "print" "interp" "pipeline" "ssa" "cfg" "licm" "strength" "unroll" "loop_simplify" "rotate" "unswitch" "loop_deletion" "cprop" "sccp" "gvn" "superlocal_lvn" "copyprop" "gdce")
 BRENCH_TESTS=("lvn_bench")
 BRENCH_CONFIG_NAME="brench.toml"
 